@@ -0,0 +1,74 @@
+use std::cmp::Ordering;
+
+use num_bigint::BigUint;
+use zk::Fr;
+
+use crate::fr_to_biguint;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Amount(pub Fr);
+
+impl Amount {
+    pub fn zero() -> Self {
+        Self(Fr::from(0u32))
+    }
+    
+    pub fn value(self) -> BigUint {
+        fr_to_biguint(self.0)
+    }
+    
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        let sum = Amount(self.0 + other.0);
+        (sum.value() == self.value() + other.value()).then_some(sum)
+    }
+    
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        (self >= other).then(|| Amount(self.0 - other.0))
+    }
+}
+
+impl Eq for Amount {}
+
+impl PartialOrd for Amount {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Amount {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value().cmp(&other.value())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn near_modulus() -> Amount {
+        Amount(Fr::from(0u32) - Fr::from(1u32))
+    }
+    
+    #[test]
+    fn ordering_compares_magnitudes() {
+        let a = |n: u32| Amount(Fr::from(n));
+        assert_eq!(a(7).cmp(&a(7)), Ordering::Equal);
+        assert!(a(7) < a(8) && a(8) > a(7));
+        assert!(a(255) < a(256));
+        assert!(near_modulus() > a(1));
+        assert!(Amount(near_modulus().0 - Fr::from(1u32)) < near_modulus());
+    }
+    
+    #[test]
+    fn checked_arithmetic_refuses_to_wrap() {
+        let a = |n: u32| Amount(Fr::from(n));
+        assert!(a(5).checked_sub(a(5)) == Some(Amount::zero()));
+        assert!(a(5).checked_sub(a(3)) == Some(a(2)));
+        assert!(a(5).checked_sub(a(6)).is_none());
+        assert!(Amount::zero().checked_sub(a(1)).is_none());
+        
+        assert!(a(2).checked_add(a(3)) == Some(a(5)));
+        assert!(near_modulus().checked_add(Amount::zero()) == Some(near_modulus()));
+        assert!(near_modulus().checked_add(a(1)).is_none());
+    }
+}
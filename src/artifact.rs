@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use l0::AsBytes;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use zk::Fr;
+
+use crate::HexConverter;
+
+pub const ARTIFACT_VERSION: u8 = 1;
+
+// Written by build-unsigned on the online machine. The change owner is left
+// out: sign-tx fills it in from the secret's derived address.
+#[derive(Serialize, Deserialize)]
+pub struct UnsignedTx {
+    pub version: u8,
+    pub from: String,
+    pub inputs: Vec<UnsignedInput>,
+    pub to: String,
+    pub amount: String,
+    pub fee: String,
+    pub change: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnsignedInput {
+    pub id: String,
+    pub amount: String,
+}
+
+// Written by sign-tx on the offline machine; submit-tx only broadcasts it.
+#[derive(Serialize, Deserialize)]
+pub struct SignedTx {
+    pub version: u8,
+    pub tx_hash: String,
+    pub wp_tx: String,
+}
+
+// Same checks as parse_address: a short, over-length or out-of-field value
+// would otherwise decode to some other number.
+pub fn fr(field: &str, hex: &str) -> Result<Fr> {
+    let bytes = hex::decode(hex).map_err(|err| anyhow!("Invalid {} {:?}: {}", field, hex, err))?;
+    if bytes.len() != 32 {
+        return Err(anyhow!("Invalid {} {:?}: expected 32 bytes, got {}", field, hex, bytes.len()));
+    }
+    let value = Fr::dec(&mut bytes.clone().into_iter())
+        .map_err(|_| anyhow!("Invalid {} {:?}: not a canonical field element", field, hex))?;
+    if value.to_hex() != hex::encode(&bytes) {
+        return Err(anyhow!("Invalid {} {:?}: not a canonical field element", field, hex));
+    }
+    Ok(value)
+}
+
+pub fn write<T: Serialize>(path: &Path, artifact: &T) -> Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(artifact)?)
+        .map_err(|err| anyhow!("Failed to write {}: {}", path.display(), err))
+}
+
+pub trait Versioned {
+    fn version(&self) -> u8;
+}
+
+impl Versioned for UnsignedTx {
+    fn version(&self) -> u8 {
+        self.version
+    }
+}
+
+impl Versioned for SignedTx {
+    fn version(&self) -> u8 {
+        self.version
+    }
+}
+
+pub fn read<T: DeserializeOwned + Versioned>(path: &Path) -> Result<T> {
+    let raw = fs::read(path).map_err(|err| anyhow!("Failed to read {}: {}", path.display(), err))?;
+    let artifact: T = serde_json::from_slice(&raw)
+        .map_err(|err| anyhow!("{} is not a valid artifact: {}", path.display(), err))?;
+    if artifact.version() != ARTIFACT_VERSION {
+        return Err(anyhow!(
+            "{} has artifact version {}, expected {}",
+            path.display(),
+            artifact.version(),
+            ARTIFACT_VERSION
+        ));
+    }
+    Ok(artifact)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fr_accepts_canonical_32_byte_values() {
+        let eight = format!("{:0>64}", "08");
+        assert_eq!(fr("amount", &eight).unwrap(), Fr::from(8u32));
+    }
+
+    #[test]
+    fn fr_rejects_wrong_lengths_and_non_canonical_values() {
+        assert!(fr("amount", "08").is_err());
+        assert!(fr("amount", &format!("{:0>66}", "08")).is_err());
+        assert!(fr("amount", &"ff".repeat(32)).is_err());
+        assert!(fr("amount", "zz").is_err());
+    }
+}
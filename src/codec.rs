@@ -0,0 +1,20 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+// Version of the archive record layout. It lives in the record's
+// `wire_version` field; the archived bytes themselves are exactly what was
+// submitted.
+pub const WIRE_VERSION: u8 = 1;
+
+// Records written before the field existed share the current layout.
+pub fn check_version(record: &Value) -> Result<()> {
+    match record.get("wire_version") {
+        None => Ok(()),
+        Some(version) if version.as_u64() == Some(WIRE_VERSION as u64) => Ok(()),
+        Some(version) => Err(anyhow!(
+            "unsupported wire format v{} (this wallet understands v{})",
+            version,
+            WIRE_VERSION
+        )),
+    }
+}
@@ -0,0 +1,39 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+// Defaults read from ~/.wallet/config.toml. Command-line flags win over the
+// file, and the file wins over environment variables.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub api_url: Option<String>,
+    pub fee: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub concurrency: Option<usize>,
+}
+
+pub fn default_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Cannot locate the home directory for the config file"))?;
+    Ok(home.join(".wallet").join("config.toml"))
+}
+
+// A missing default file just means no defaults; a missing --config file is
+// an error.
+pub fn load(explicit: Option<&Path>) -> Result<Config> {
+    let path = match explicit {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let path = default_path()?;
+            if !path.exists() {
+                return Ok(Config::default());
+            }
+            path
+        }
+    };
+    
+    let raw = fs::read_to_string(&path).map_err(|err| anyhow!("Failed to read config {}: {}", path.display(), err))?;
+    toml::from_str(&raw).map_err(|err| anyhow!("Invalid config {}: {}", path.display(), err))
+}
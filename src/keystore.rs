@@ -0,0 +1,217 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use l0::AsBytes;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::{json, Value};
+use zeroize::Zeroizing;
+use zk::Fr;
+
+use crate::secret::Secret;
+
+const KEYSTORE_VERSION: u64 = 1;
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+pub fn keystore_path(account: &str) -> Result<PathBuf> {
+    Ok(keystore_dir()?.join(format!("{}.json", account)))
+}
+
+#[cfg(not(test))]
+fn keystore_dir() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Cannot locate the home directory for the keystore"))?;
+    Ok(home.join(".wallet").join("keystore"))
+}
+
+// Tests get a keystore of their own rather than the user's.
+#[cfg(test)]
+fn keystore_dir() -> Result<PathBuf> {
+    Ok(std::env::temp_dir().join(format!("wallet-keystore-{}", std::process::id())))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<Zeroizing<[u8; 32]>> {
+    let params = scrypt::Params::new(log_n, r, p, 32).map_err(|err| anyhow!("Invalid scrypt parameters: {}", err))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut *key)
+        .map_err(|err| anyhow!("Key derivation failed: {}", err))?;
+    Ok(key)
+}
+
+fn cipher(key: &[u8; 32]) -> Result<ChaCha20Poly1305> {
+    ChaCha20Poly1305::new_from_slice(key).map_err(|_| anyhow!("Derived key has the wrong length"))
+}
+
+pub fn store(account: &str, secret: &Secret, passphrase: &str) -> Result<PathBuf> {
+    let record = encrypt(account, secret, passphrase)?;
+    let path = keystore_path(account)?;
+    write_record(&path, &record, false)?;
+    Ok(path)
+}
+
+pub fn encrypt(account: &str, secret: &Secret, passphrase: &str) -> Result<Value> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+    
+    let key = derive_key(passphrase, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+    let plaintext = Zeroizing::new(secret.expose().enc().collect::<Vec<u8>>());
+    let ciphertext = cipher(&key)?
+        .encrypt(&Nonce::from(nonce), plaintext.as_slice())
+        .map_err(|_| anyhow!("Failed to encrypt the secret"))?;
+    
+    Ok(json!({
+        "version": KEYSTORE_VERSION,
+        "account": account,
+        "kdf": "scrypt",
+        "kdf_params": { "log_n": SCRYPT_LOG_N, "r": SCRYPT_R, "p": SCRYPT_P },
+        "salt": hex::encode(salt),
+        "cipher": "chacha20poly1305",
+        "nonce": hex::encode(nonce),
+        "ciphertext": hex::encode(ciphertext),
+    }))
+}
+
+fn write_record(path: &Path, record: &Value, overwrite: bool) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    if overwrite {
+        options.write(true).create(true).truncate(true);
+    } else {
+        options.write(true).create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|err| anyhow!("Failed to create keystore {}: {}", path.display(), err))?;
+    file.write_all(&serde_json::to_vec_pretty(record)?)?;
+    Ok(())
+}
+
+pub fn read_record(path: &Path) -> Result<Value> {
+    let record: Value = serde_json::from_slice(
+        &fs::read(path).map_err(|err| anyhow!("Failed to read keystore {}: {}", path.display(), err))?,
+    )?;
+    if record["version"].as_u64() != Some(KEYSTORE_VERSION) {
+        return Err(anyhow!("Unsupported keystore version in {}", path.display()));
+    }
+    Ok(record)
+}
+
+pub fn load(account: &str, passphrase: &str) -> Result<Secret> {
+    let path = keystore_path(account)?;
+    decrypt(&read_record(&path)?, &path, passphrase)
+}
+
+// The exported file is the encrypted record itself, so restoring it needs
+// the same passphrase. Decrypting first only proves the caller knows it.
+pub fn export(account: &str, out: &Path, passphrase: &str) -> Result<()> {
+    let path = keystore_path(account)?;
+    let record = read_record(&path)?;
+    decrypt(&record, &path, passphrase)?;
+    write_record(out, &record, false)
+}
+
+pub fn import(account: &str, record: &Value, force: bool) -> Result<PathBuf> {
+    let path = keystore_path(account)?;
+    if path.exists() && !force {
+        return Err(anyhow!("Account {} is already in the keystore; pass --force to overwrite it", account));
+    }
+    write_record(&path, record, force)?;
+    Ok(path)
+}
+
+pub fn decrypt(record: &Value, path: &Path, passphrase: &str) -> Result<Secret> {
+    let field = |name: &str| -> Result<Vec<u8>> {
+        let value = record[name]
+            .as_str()
+            .ok_or_else(|| anyhow!("Keystore {} has no {} field", path.display(), name))?;
+        Ok(hex::decode(value)?)
+    };
+    let param = |name: &str| -> Result<u64> {
+        record["kdf_params"][name]
+            .as_u64()
+            .ok_or_else(|| anyhow!("Keystore {} has no kdf_params.{}", path.display(), name))
+    };
+    
+    let nonce: [u8; 12] = field("nonce")?
+        .try_into()
+        .map_err(|_| anyhow!("Keystore {} has a malformed nonce", path.display()))?;
+    let key = derive_key(
+        passphrase,
+        &field("salt")?,
+        u8::try_from(param("log_n")?)?,
+        u32::try_from(param("r")?)?,
+        u32::try_from(param("p")?)?,
+    )?;
+    let plaintext = Zeroizing::new(
+        cipher(&key)?
+            .decrypt(&Nonce::from(nonce), field("ciphertext")?.as_slice())
+            .map_err(|_| anyhow!("Wrong passphrase or corrupted keystore {}", path.display()))?,
+    );
+    
+    Ok(Secret::new(Fr::dec(&mut plaintext.iter().copied())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn encrypted_secret_decrypts_only_with_its_passphrase() {
+        let secret = Secret::new(Fr::from(0xdead_beefu32));
+        let record = encrypt("account", &secret, "correct horse").unwrap();
+        let path = Path::new("account.json");
+        
+        assert_eq!(record["account"], "account");
+        assert_eq!(record["version"], KEYSTORE_VERSION);
+        assert!(!record["ciphertext"].as_str().unwrap().contains(&*secret.to_hex()));
+        
+        assert!(decrypt(&record, path, "correct horse").unwrap().expose() == secret.expose());
+        assert!(decrypt(&record, path, "wrong").is_err());
+        
+        let mut tampered = record.clone();
+        tampered["salt"] = json!(hex::encode([0u8; 16]));
+        assert!(decrypt(&tampered, path, "correct horse").is_err());
+    }
+    
+    #[test]
+    fn exported_account_imports_back_and_import_needs_force_to_overwrite() {
+        let (account, other) = ("export-import", "export-import-other");
+        let secret = Secret::new(Fr::from(0x5eedu32));
+        let out = std::env::temp_dir().join(format!("wallet-export-{}.json", std::process::id()));
+        store(account, &secret, "pass").unwrap();
+        
+        assert!(export(account, &out, "wrong").is_err());
+        export(account, &out, "pass").unwrap();
+        assert!(export(account, &out, "pass").is_err());
+        fs::remove_file(keystore_path(account).unwrap()).unwrap();
+        
+        let record = read_record(&out).unwrap();
+        import(account, &record, false).unwrap();
+        assert!(load(account, "pass").unwrap().expose() == secret.expose());
+        
+        store(other, &Secret::new(Fr::from(1u32)), "pass").unwrap();
+        let err = import(other, &record, false).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{}", err);
+        assert!(load(other, "pass").unwrap().expose() == Fr::from(1u32));
+        import(other, &record, true).unwrap();
+        assert!(load(other, "pass").unwrap().expose() == secret.expose());
+        
+        for path in [out, keystore_path(account).unwrap(), keystore_path(other).unwrap()] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -1,54 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
+use async_trait::async_trait;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::stream::{self, StreamExt};
 use hex_str::HexString;
-use rand::rngs::OsRng;
+use rand::rngs::{OsRng, StdRng};
+use rand::{seq::SliceRandom, CryptoRng, RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use num_bigint::BigUint;
 use l0::{Tx, Out, Wp, AsBytes};
 use zk::{Fr, Vk, Proof, ToHash, Inputs, AsNum};
 use ark_std::UniformRand;
 
+#[macro_use]
+mod output;
+
+mod amount;
+mod artifact;
+mod codec;
+mod config;
+mod keystore;
+mod passphrase;
+mod secret;
+mod uri;
 mod wallet_prover_ffi;
 
+use amount::Amount;
+use artifact::{SignedTx, UnsignedInput, UnsignedTx};
+use secret::Secret;
+use zeroize::Zeroizing;
+use uri::PaymentRequest;
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
     #[arg(long, env = "API_HTTP_URL", default_value = "http://localhost:8080")]
     api_url: String,
 
+    #[arg(long, default_value_t = 30)]
+    timeout_secs: u64,
+
+    #[arg(long, default_value_t = 3, help = "Retries for connection failures and 5xx responses")]
+    retries: usize,
+
+    #[arg(long, default_value_t = 120)]
+    submit_timeout: u64,
+
+    #[arg(long, default_value_t = MAX_BACKOFF_MS, help = "Longest single wait between retries")]
+    max_backoff_ms: u64,
+
+    #[arg(long, help = "Stop retrying and polling once this many seconds have passed")]
+    deadline_secs: Option<u64>,
+
+    #[arg(long)]
+    archive_dir: Option<PathBuf>,
+
+    #[arg(long, requires = "archive_dir")]
+    archive_gzip: bool,
+
+    #[arg(long)]
+    no_network: bool,
+
+    #[arg(long, value_enum, default_value_t = HashFormat::Hex)]
+    hash_format: HashFormat,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
+    #[arg(long, default_value_t = 3000)]
+    tail_cache_ms: u64,
+
+    #[arg(long, help = "Cache the prover's verifying key at this path (one file per circuit)")]
+    vk_cache: Option<PathBuf>,
+
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[arg(long, global = true, help = "Config file with defaults (defaults to ~/.wallet/config.toml)")]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Create,
+    Create {
+        #[arg(long)]
+        confirm_address_derivation: bool,
+        
+        #[arg(long, help = "INSECURE, testing only: derive the secret from a fixed 32-byte seed")]
+        seed: Option<HexString>,
+        
+        #[arg(long, help = "Print the raw secret instead of encrypting it into the keystore")]
+        no_keystore: bool,
+        
+        #[command(flatten)]
+        passphrase: PassphraseArgs,
+    },
     
-    GetBalance {
+    DeriveAddress {
         #[arg(long)]
-        account: HexString,
+        secret: HexString,
     },
     
-    ListUtxos {
+    GetBalance {
         #[arg(long)]
         account: HexString,
+        
+        #[arg(long, value_enum, default_value_t = BalanceSource::Node)]
+        balance_source: BalanceSource,
     },
     
-    Transfer {
+    SendMax {
         #[arg(long)]
         from: HexString,
         
         #[arg(long)]
         to: HexString,
         
+        #[arg(long)]
+        secret: HexString,
+        
+        #[arg(long, help = "Fee to pay (defaults to 3)")]
+        fee: Option<HexString>,
+        
+        #[arg(long, help = "Build and prove the sweep, then print it instead of submitting")]
+        dry_run: bool,
+        
+        #[arg(long, help = "Submit without asking for confirmation (required with --json)")]
+        yes: bool,
+    },
+    
+    EstimateFee {
+        #[arg(long)]
+        from: HexString,
+        
         #[arg(long)]
         amount: HexString,
         
+        #[arg(long, help = "Fee to assume (defaults to 3)")]
+        fee: Option<HexString>,
+    },
+    
+    ListUtxos {
         #[arg(long)]
-        secret: HexString,
+        account: HexString,
+        
+        #[arg(long, help = "Also print each output's data entries as hex")]
+        show_data: bool,
+    },
+    
+    GetUtxo {
+        id: HexString,
+    },
+    
+    Transfer {
+        #[command(flatten)]
+        args: TransferArgs,
+        
+        #[arg(long, required_unless_present = "account")]
+        secret: Option<HexString>,
+        
+        #[arg(long, conflicts_with = "secret", help = "Load the secret from this account's keystore")]
+        account: Option<HexString>,
+        
+        #[command(flatten)]
+        passphrase: PassphraseArgs,
     },
     
     TransferPermissionless {
+        #[command(flatten)]
+        args: TransferArgs,
+    },
+    
+    TxHash {
+        tx_hex: String,
+    },
+    
+    ListMethods,
+    
+    NodeInfo,
+    
+    SimulateTx {
+        tx_hex: String,
+    },
+    
+    ShowArchive {
+        file: PathBuf,
+    },
+    
+    Coins {
+        #[arg(long)]
+        account: HexString,
+        
+        #[arg(long = "select")]
+        select: Vec<HexString>,
+        
+        #[arg(long, default_value = "coins.json")]
+        stage_file: PathBuf,
+    },
+    
+    VerifyProof {
+        tx_hex: String,
+    },
+    
+    Consolidate {
+        #[arg(long)]
+        account: HexString,
+        
+        #[arg(long)]
+        secret: HexString,
+        
+        #[arg(long, help = "Fee per merge transaction (defaults to 3)")]
+        fee: Option<HexString>,
+        
+        #[arg(long)]
+        dry_run: bool,
+        
+        #[arg(long, default_value_t = 120, help = "Seconds to wait for each merge to confirm")]
+        wait_timeout: u64,
+        
+        #[arg(long, help = "Merge without asking for confirmation (required with --json)")]
+        yes: bool,
+        
+        #[arg(long, help = "Instead of merging pairwise, build one coin of exactly this amount plus change")]
+        target: Option<HexString>,
+    },
+    
+    Receive {
+        #[arg(long)]
+        account: HexString,
+        
+        #[arg(long)]
+        amount: Option<HexString>,
+        
+        #[arg(long)]
+        memo: Option<String>,
+    },
+    
+    BuildUnsigned {
         #[arg(long)]
         from: HexString,
         
@@ -57,15 +257,316 @@ enum Commands {
         
         #[arg(long)]
         amount: HexString,
+        
+        #[arg(long, help = "Transaction fee (defaults to 3)")]
+        fee: Option<HexString>,
+        
+        #[arg(long, default_value = "unsigned.json")]
+        out: PathBuf,
+    },
+    
+    SignTx {
+        file: PathBuf,
+        
+        #[arg(long)]
+        secret: HexString,
+        
+        #[arg(long, default_value = "signed.json")]
+        out: PathBuf,
+        
+        #[arg(long, help = "Sign without asking for confirmation (required with --json)")]
+        yes: bool,
+    },
+    
+    SubmitTx {
+        file: PathBuf,
+    },
+    
+    ExportAccount {
+        #[arg(long)]
+        account: HexString,
+        
+        #[arg(long)]
+        out: PathBuf,
+        
+        #[command(flatten)]
+        passphrase: PassphraseArgs,
+    },
+    
+    ImportAccount {
+        file: PathBuf,
+        
+        #[arg(long, help = "Overwrite the account if it is already in the keystore")]
+        force: bool,
+        
+        #[command(flatten)]
+        passphrase: PassphraseArgs,
     },
 }
 
+impl Cli {
+    fn apply_config(&mut self, config: &config::Config, matches: &ArgMatches) -> Result<()> {
+        let from_flag = |matches: &ArgMatches, id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        
+        if let Some(api_url) = &config.api_url {
+            if !from_flag(matches, "api_url") {
+                self.api_url = api_url.clone();
+            }
+        }
+        if let Some(timeout_secs) = config.timeout_secs {
+            if !from_flag(matches, "timeout_secs") {
+                self.timeout_secs = timeout_secs;
+            }
+        }
+        
+        let Some((_, sub_matches)) = matches.subcommand() else {
+            return Ok(());
+        };
+        let fee: Option<HexString> = config
+            .fee
+            .as_ref()
+            .map(|fee| fee.parse().map_err(|_| anyhow!("Invalid fee {} in config", fee)))
+            .transpose()?;
+        match &mut self.command {
+            Commands::Transfer { args, .. } | Commands::TransferPermissionless { args } => {
+                if let Some(concurrency) = config.concurrency {
+                    if !from_flag(sub_matches, "concurrency") {
+                        args.concurrency = concurrency;
+                    }
+                }
+                if args.fee.is_none() {
+                    args.fee = fee;
+                }
+            }
+            Commands::Consolidate { fee: flag_fee @ None, .. }
+            | Commands::BuildUnsigned { fee: flag_fee @ None, .. }
+            | Commands::EstimateFee { fee: flag_fee @ None, .. }
+            | Commands::SendMax { fee: flag_fee @ None, .. } => {
+                *flag_fee = fee;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct TlsArgs {
+    #[arg(long)]
+    tls_ca_cert: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "tls_ca_cert")]
+    danger_accept_invalid_certs: bool,
+}
+
+#[derive(Args)]
+struct PassphraseArgs {
+    #[arg(long, env = "KEYSTORE_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+    
+    #[arg(long, help = "Prompt for every keystore operation instead of once per run")]
+    no_cache_passphrase: bool,
+}
+
+impl PassphraseArgs {
+    fn read(&self, confirm: bool) -> Result<Zeroizing<String>> {
+        self.read_with(confirm, &mut |prompt| Ok(rpassword::prompt_password(prompt)?))
+    }
+    
+    // A new passphrase (confirm) is always typed twice; an existing one is
+    // reused from earlier in the run unless caching is turned off.
+    fn read_with(&self, confirm: bool, prompt: &mut dyn FnMut(&str) -> Result<String>) -> Result<Zeroizing<String>> {
+        if let Some(passphrase) = &self.passphrase {
+            return Ok(Zeroizing::new(passphrase.clone()));
+        }
+        if !confirm && !self.no_cache_passphrase {
+            if let Some(passphrase) = passphrase::cached() {
+                return Ok(passphrase);
+            }
+        }
+        
+        let passphrase = Zeroizing::new(prompt("Keystore passphrase: ")?);
+        if confirm {
+            let repeated = Zeroizing::new(prompt("Repeat passphrase: ")?);
+            if *repeated != *passphrase {
+                return Err(anyhow!("Passphrases do not match"));
+            }
+        }
+        if !self.no_cache_passphrase {
+            passphrase::remember(&passphrase);
+        }
+        Ok(passphrase)
+    }
+    
+    // Runs a keystore operation, dropping a cached passphrase it rejects so
+    // the next attempt prompts again.
+    fn unlock<T>(&self, operation: impl FnOnce(&str) -> Result<T>) -> Result<T> {
+        let passphrase = self.read(false)?;
+        operation(&passphrase).inspect_err(|_| passphrase::forget())
+    }
+}
+
+#[derive(Args)]
+struct TransferArgs {
+    #[arg(long)]
+    from: HexString,
+    
+    #[arg(long)]
+    to: HexString,
+    
+    #[arg(long)]
+    amount: HexString,
+    
+    #[arg(long, conflicts_with = "repeat")]
+    idempotency_key: Option<String>,
+    
+    #[arg(long)]
+    simulate: bool,
+    
+    #[arg(long, conflicts_with = "simulate", help = "Build and prove the transfer, then print it instead of submitting")]
+    dry_run: bool,
+    
+    #[arg(long, default_value_t = 1)]
+    repeat: usize,
+    
+    #[arg(long = "input")]
+    inputs: Vec<HexString>,
+    
+    #[arg(long)]
+    inputs_file: Option<PathBuf>,
+    
+    #[arg(long)]
+    skip_conservation_check: bool,
+    
+    #[arg(long)]
+    no_roundtrip_check: bool,
+    
+    #[arg(long, default_value_t = 8, help = "Maximum concurrent UTXO fetches")]
+    concurrency: usize,
+    
+    #[arg(long)]
+    allow_self: bool,
+    
+    #[arg(long)]
+    verify_before_submit: bool,
+    
+    #[arg(long, value_name = "ADDR:AMOUNT")]
+    second_output: Option<String>,
+    
+    #[arg(long, help = "Fee to pay (defaults to 3)")]
+    fee: Option<HexString>,
+    
+    #[arg(long, value_enum, default_value_t = InputOrder::Natural, hide = true)]
+    input_order: InputOrder,
+    
+    #[arg(long, alias = "strategy", value_enum, default_value_t = CoinSelection::FirstFit)]
+    coin_selection: CoinSelection,
+    
+    #[arg(long, help = "Seed for --coin-selection random (testing only)")]
+    selection_seed: Option<u64>,
+    
+    #[arg(long, help = "Submit without asking for confirmation (required with --json)")]
+    yes: bool,
+    
+    #[arg(long, help = "After submitting, wait up to this many seconds for the inputs to be spent")]
+    wait_timeout: Option<u64>,
+}
+
+impl TransferArgs {
+    fn fee(&self) -> Result<Fr> {
+        parse_fee(self.fee.as_ref())
+    }
+    
+    fn second_output(&self) -> Result<Option<(Fr, Fr)>> {
+        let Some(spec) = &self.second_output else {
+            return Ok(None);
+        };
+        let (addr, amount) = spec
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid --second-output {}: expected <addr>:<amount>", spec))?;
+        let addr: HexString = addr
+            .parse()
+            .map_err(|_| anyhow!("Invalid --second-output address: {}", addr))?;
+        let amount: HexString = amount
+            .parse()
+            .map_err(|_| anyhow!("Invalid --second-output amount: {}", amount))?;
+        
+        Ok(Some((parse_address(&addr)?, HexConverter::from_hex(amount)?)))
+    }
+    
+    fn staged_inputs(&self) -> Result<Vec<Fr>> {
+        let mut staged = Vec::new();
+        for input in &self.inputs {
+            staged.push(HexConverter::from_hex(input.clone())?);
+        }
+        
+        if let Some(path) = &self.inputs_file {
+            let record: Value = serde_json::from_slice(&fs::read(path)?)?;
+            let ids = record["inputs"]
+                .as_array()
+                .ok_or_else(|| anyhow!("{} has no inputs array", path.display()))?;
+            for id in ids {
+                let id = id.as_str().ok_or_else(|| anyhow!("Invalid input id in {}", path.display()))?;
+                let bytes = hex::decode(id)?;
+                staged.push(Fr::dec(&mut bytes.into_iter())?);
+            }
+        }
+        
+        Ok(staged)
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BalanceSource {
+    Node,
+    Utxos,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CoinSelection {
+    FirstFit,
+    Random,
+    LargestFirst,
+    SmallestFirst,
+    BranchAndBound,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputOrder {
+    Natural,
+    Swapped,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HashFormat {
+    Hex,
+    Prefixed,
+    Base58,
+}
+
+impl Commands {
+    fn is_offline(&self) -> bool {
+        matches!(
+            self,
+            Commands::Create { .. }
+                | Commands::TxHash { .. }
+                | Commands::ShowArchive { .. }
+                | Commands::Receive { .. }
+                | Commands::VerifyProof { .. }
+                | Commands::SignTx { .. }
+                | Commands::ExportAccount { .. }
+                | Commands::ImportAccount { .. }
+                | Commands::DeriveAddress { .. }
+        )
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct JsonRpcRequest {
     jsonrpc: String,
     method: String,
     params: Value,
-    id: i32,
+    id: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -73,78 +574,507 @@ struct JsonRpcResponse {
     jsonrpc: String,
     result: Option<Value>,
     error: Option<Value>,
-    id: i32,
+    id: Option<u64>,
 }
 
-struct ApiClient {
-    url: String,
-    client: reqwest::Client,
+#[derive(Debug)]
+struct RpcError(Value);
+
+impl RpcError {
+    fn is_method_not_found(&self) -> bool {
+        self.0["code"].as_i64() == Some(-32601)
+    }
 }
 
-impl ApiClient {
-    fn new(url: String) -> Self {
-        Self {
-            url,
-            client: reqwest::Client::new(),
-        }
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RPC error: {:?}", self.0)
     }
+}
 
-    async fn call_rpc(&self, method: &str, params: Value) -> Result<Value> {
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: method.to_string(),
-            params,
-            id: 1,
-        };
+impl std::error::Error for RpcError {}
 
-        let response = self.client
-            .post(&self.url)
-            .json(&request)
-            .send()
-            .await?
-            .json::<JsonRpcResponse>()
-            .await?;
+const INVALID_JSON_RETRIES: usize = 2;
 
-        if let Some(error) = response.error {
-            return Err(anyhow!("RPC error: {:?}", error));
-        }
+#[derive(Debug)]
+struct InvalidJsonResponse {
+    method: String,
+    reason: String,
+    snippet: String,
+}
 
-        response.result.ok_or_else(|| anyhow!("No result in response"))
+impl std::fmt::Display for InvalidJsonResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid JSON response to {}: {} (body starts with {:?})", self.method, self.reason, self.snippet)
     }
+}
 
-    async fn get_balance(&self, owner: &str) -> Result<String> {
-        let result = self.call_rpc(
-            "get_balance_by_owner",
-            json!({
-                "addr": owner
-            })
-        ).await?;
-        
-        Ok(result.as_str().unwrap_or("").to_string())
+impl std::error::Error for InvalidJsonResponse {}
+
+#[derive(Debug)]
+struct HttpStatusError {
+    method: String,
+    status: u16,
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed with HTTP status {}", self.method, self.status)
     }
+}
 
-    async fn get_utxos_paginated(&self, last_utxo_id: &str, owner: &str) -> Result<(Vec<String>, Option<String>)> {
-        let result = self.call_rpc(
-            "get_list_of_utxo_by_owner_paginated",
-            json!({
-                "last_utxo_id": last_utxo_id,
-                "owner": owner
-            })
-        ).await?;
+impl std::error::Error for HttpStatusError {}
 
-        let utxos = result["utxos"]
-            .as_array()
-            .ok_or_else(|| anyhow!("Invalid utxos format"))?
-            .iter()
-            .map(|v| v.as_str().unwrap_or("").to_string())
-            .collect();
+#[derive(Debug)]
+struct BatchUnsupported {
+    snippet: String,
+}
 
-        let last = result["last_utxo_id"].as_str().map(|s| s.to_string());
+impl std::fmt::Display for BatchUnsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "node answered an RPC batch with a non-array reply (body starts with {:?})", self.snippet)
+    }
+}
 
-        Ok((utxos, last))
+impl std::error::Error for BatchUnsupported {}
+
+#[derive(Debug)]
+struct SubmitTimeout {
+    secs: u64,
+}
+
+impl std::fmt::Display for SubmitTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "submission timed out after {}s (the tx may still have been accepted, check the account's UTXOs before resubmitting)",
+            self.secs
+        )
     }
-    
-    async fn get_next_id_of_utxo_by_owner(&self, utxo_id: &str, owner: &str) -> Result<Option<String>> {
+}
+
+impl std::error::Error for SubmitTimeout {}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Submission {
+    New,
+    AlreadyKnown,
+}
+
+impl Submission {
+    fn status(self) -> &'static str {
+        match self {
+            Submission::New => "submitted",
+            Submission::AlreadyKnown => "already_submitted",
+        }
+    }
+}
+
+const BASE_BACKOFF_MS: u64 = 250;
+const MAX_BACKOFF_MS: u64 = 10_000;
+
+// Uncapped exponential backoff for retry `attempt` (0-based); the cap is the
+// policy's `max_backoff`. Saturates rather than overflowing for large attempts.
+fn backoff(attempt: usize) -> Duration {
+    let shift = attempt.min(63) as u32;
+    Duration::from_millis(BASE_BACKOFF_MS.saturating_mul(1 << shift))
+}
+
+#[derive(Clone, Copy)]
+struct RetryPolicy {
+    retries: usize,
+    max_backoff: Duration,
+    deadline: Option<Instant>,
+}
+
+impl RetryPolicy {
+    // The wait before retry `attempt`, or None once the attempts are used up
+    // or waiting would run past the deadline.
+    fn next_backoff(&self, attempt: usize) -> Option<Duration> {
+        if attempt >= self.retries {
+            return None;
+        }
+        let backoff = backoff(attempt).min(self.max_backoff);
+        match self.deadline {
+            Some(deadline) => match Instant::now().checked_add(backoff) {
+                Some(resume) if resume < deadline => Some(backoff),
+                _ => None,
+            },
+            None => Some(backoff),
+        }
+    }
+    
+    // `limit`, shortened to whatever is left before the deadline.
+    fn bound(&self, limit: Duration) -> Duration {
+        match self.deadline {
+            Some(deadline) => limit.min(deadline.saturating_duration_since(Instant::now())),
+            None => limit,
+        }
+    }
+}
+
+// JSON-RPC application errors are never transient: the node answered.
+fn is_transient(err: &anyhow::Error) -> bool {
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        return e.is_connect() || e.is_timeout() || e.status().is_some_and(|status| status.is_server_error());
+    }
+    if let Some(e) = err.downcast_ref::<HttpStatusError>() {
+        return e.status >= 500;
+    }
+    err.is::<std::io::Error>() || err.is::<hyper::Error>() || err.is::<tokio::time::error::Elapsed>()
+}
+
+fn is_method_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<RpcError>().is_some_and(|e| e.is_method_not_found())
+}
+
+fn is_batch_unsupported(err: &anyhow::Error) -> bool {
+    err.is::<BatchUnsupported>() || is_method_not_found(err)
+}
+
+#[cfg(unix)]
+async fn post_unix(socket: &Path, body: Vec<u8>) -> Result<(u16, String)> {
+    let stream = tokio::net::UnixStream::connect(socket).await?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            log::warn!("API socket connection error: {}", err);
+        }
+    });
+    
+    let request = hyper::Request::post("/")
+        .header(hyper::header::HOST, "localhost")
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(body))?;
+    let response = sender.send_request(request).await?;
+    let status = response.status().as_u16();
+    let bytes = hyper::body::to_bytes(response.into_body()).await?;
+    Ok((status, String::from_utf8(bytes.to_vec())?))
+}
+
+#[cfg(not(unix))]
+async fn post_unix(_socket: &Path, _body: Vec<u8>) -> Result<(u16, String)> {
+    Err(anyhow!("unix:// API URLs are only supported on Unix"))
+}
+
+#[async_trait]
+trait RpcTransport: Send + Sync {
+    async fn call(&self, method: &str, params: Value) -> Result<Value>;
+    
+    // Like `call`, but with its own per-request timeout in place of the
+    // transport's default.
+    async fn call_with_timeout(&self, method: &str, params: Value, timeout: Duration) -> Result<Value>;
+    
+    async fn call_batch(&self, calls: &[(&str, Value)]) -> Result<Vec<Result<Value>>> {
+        let mut results = Vec::with_capacity(calls.len());
+        for (method, params) in calls {
+            results.push(self.call(method, params.clone()).await);
+        }
+        Ok(results)
+    }
+}
+
+// JSON-RPC over HTTP (reqwest) or over a unix socket.
+struct HttpTransport {
+    url: String,
+    socket: Option<PathBuf>,
+    client: reqwest::Client,
+    timeout: Duration,
+    offline: bool,
+    next_id: AtomicU64,
+}
+
+impl HttpTransport {
+    fn new(url: String, timeout: Duration, offline: bool, tls: &TlsArgs) -> Result<Self> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout);
+        if let Some(path) = &tls.tls_ca_cert {
+            let pem = fs::read(path).map_err(|err| anyhow!("Failed to read TLS CA certificate {}: {}", path.display(), err))?;
+            let cert = reqwest::Certificate::from_pem(&pem)
+                .map_err(|err| anyhow!("Invalid TLS CA certificate {}: {}", path.display(), err))?;
+            builder = builder.tls_built_in_root_certs(false).add_root_certificate(cert);
+        }
+        if tls.danger_accept_invalid_certs {
+            log::warn!("TLS certificate verification is DISABLED (--danger-accept-invalid-certs); use only for local testing");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        
+        let socket = url.strip_prefix("unix://").map(PathBuf::from);
+        if let Some(path) = &socket {
+            if !cfg!(unix) {
+                return Err(anyhow!("unix:// API URLs are only supported on Unix"));
+            }
+            if !offline && !path.exists() {
+                return Err(anyhow!("API socket {} does not exist", path.display()));
+            }
+        }
+
+        Ok(Self {
+            url,
+            socket,
+            client: builder.build()?,
+            timeout,
+            offline,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    async fn call_once(&self, method: &str, params: Value, timeout: Option<Duration>) -> Result<Value> {
+        if self.offline {
+            return Err(anyhow!("Refusing to call {}: network access is disabled (--no-network)", method));
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params,
+            id,
+        };
+
+        let (status, body) = self.post(serde_json::to_vec(&request)?, timeout).await?;
+        let response: JsonRpcResponse = match serde_json::from_str(&body) {
+            Ok(response) => response,
+            Err(_) if status >= 500 => {
+                return Err(HttpStatusError { method: method.to_string(), status }.into());
+            }
+            Err(err) => {
+                return Err(InvalidJsonResponse {
+                    method: method.to_string(),
+                    reason: err.to_string(),
+                    snippet: body.chars().take(200).collect(),
+                }.into());
+            }
+        };
+
+        if let Some(error) = response.error {
+            return Err(RpcError(error).into());
+        }
+        if response.id != Some(id) {
+            return Err(anyhow!("{} response id {:?} does not match request id {}", method, response.id, id));
+        }
+
+        response.result.ok_or_else(|| anyhow!("No result in response"))
+    }
+
+    async fn post(&self, body: Vec<u8>, timeout: Option<Duration>) -> Result<(u16, String)> {
+        match &self.socket {
+            Some(socket) => {
+                let send = post_unix(socket, body);
+                tokio::time::timeout(timeout.unwrap_or(self.timeout), send).await?
+            }
+            None => {
+                let mut builder = self.client
+                    .post(&self.url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body);
+                if let Some(timeout) = timeout {
+                    builder = builder.timeout(timeout);
+                }
+                
+                let response = builder.send().await?;
+                let status = response.status().as_u16();
+                Ok((status, response.text().await?))
+            }
+        }
+    }
+
+    async fn call_batch_once(&self, calls: &[(&str, Value)]) -> Result<Vec<Result<Value>>> {
+        if self.offline {
+            return Err(anyhow!("Refusing to send an RPC batch: network access is disabled (--no-network)"));
+        }
+
+        let requests: Vec<JsonRpcRequest> = calls
+            .iter()
+            .map(|(method, params)| JsonRpcRequest {
+                jsonrpc: "2.0".to_string(),
+                method: method.to_string(),
+                params: params.clone(),
+                id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            })
+            .collect();
+
+        let (status, body) = self.post(serde_json::to_vec(&requests)?, None).await?;
+        parse_batch_reply(&requests, status, &body)
+    }
+}
+
+// Answers are matched to requests by id, so results come back in request
+// order whatever order the node answers in. A failed call only fails its
+// own slot.
+fn parse_batch_reply(requests: &[JsonRpcRequest], status: u16, body: &str) -> Result<Vec<Result<Value>>> {
+    let invalid = |reason: String| InvalidJsonResponse {
+        method: "batch".to_string(),
+        reason,
+        snippet: body.chars().take(200).collect(),
+    };
+    let reply: Value = match serde_json::from_str(body) {
+        Ok(reply) => reply,
+        Err(_) if status >= 500 => {
+            return Err(HttpStatusError { method: "batch".to_string(), status }.into());
+        }
+        Err(err) => return Err(invalid(err.to_string()).into()),
+    };
+    // A node without batch support answers the array with a single error object.
+    let Value::Array(items) = reply else {
+        return Err(BatchUnsupported { snippet: body.chars().take(200).collect() }.into());
+    };
+    let responses = items
+        .into_iter()
+        .map(serde_json::from_value::<JsonRpcResponse>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid(err.to_string()))?;
+
+    let mut by_id: HashMap<u64, JsonRpcResponse> = responses
+        .into_iter()
+        .filter_map(|response| response.id.map(|id| (id, response)))
+        .collect();
+    Ok(requests
+        .iter()
+        .map(|request| {
+            let response = by_id
+                .remove(&request.id)
+                .ok_or_else(|| anyhow!("Batch response has no answer for {} (id {})", request.method, request.id))?;
+            if let Some(error) = response.error {
+                return Err(RpcError(error).into());
+            }
+            response.result.ok_or_else(|| anyhow!("No result in response"))
+        })
+        .collect())
+}
+
+#[async_trait]
+impl RpcTransport for HttpTransport {
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        self.call_once(method, params, None).await
+    }
+    
+    async fn call_with_timeout(&self, method: &str, params: Value, timeout: Duration) -> Result<Value> {
+        self.call_once(method, params, Some(timeout)).await
+    }
+    
+    async fn call_batch(&self, calls: &[(&str, Value)]) -> Result<Vec<Result<Value>>> {
+        self.call_batch_once(calls).await
+    }
+}
+
+struct PageEntry {
+    id: Option<String>,
+    utxo: String,
+}
+
+struct ApiClient {
+    transport: Box<dyn RpcTransport>,
+    retry: RetryPolicy,
+    submit_timeout: Duration,
+    light_utxo_query: AtomicBool,
+    batch_supported: AtomicBool,
+    tail_ttl: Duration,
+    tail_cache: Mutex<Option<(Instant, String)>>,
+}
+
+impl ApiClient {
+    fn new(
+        url: String,
+        timeout: Duration,
+        retry: RetryPolicy,
+        submit_timeout: Duration,
+        offline: bool,
+        tls: &TlsArgs,
+        tail_ttl: Duration,
+    ) -> Result<Self> {
+        let transport = HttpTransport::new(url, timeout, offline, tls)?;
+        Ok(Self::with_transport(Box::new(transport), retry, submit_timeout, tail_ttl))
+    }
+
+    fn with_transport(transport: Box<dyn RpcTransport>, retry: RetryPolicy, submit_timeout: Duration, tail_ttl: Duration) -> Self {
+        Self {
+            transport,
+            retry,
+            submit_timeout,
+            light_utxo_query: AtomicBool::new(true),
+            batch_supported: AtomicBool::new(true),
+            tail_ttl,
+            tail_cache: Mutex::new(None),
+        }
+    }
+
+    async fn call_rpc(&self, method: &str, params: Value) -> Result<Value> {
+        self.with_retries(|| self.transport.call(method, params.clone())).await
+    }
+
+    async fn with_retries<T, F, Fut>(&self, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        let mut transient_attempt = 0;
+        loop {
+            match call().await {
+                Err(err) if err.is::<InvalidJsonResponse>() && attempt < INVALID_JSON_RETRIES => {
+                    attempt += 1;
+                    log::warn!("{}; retrying ({}/{})", err, attempt, INVALID_JSON_RETRIES);
+                }
+                Err(err) if is_transient(&err) => {
+                    let Some(backoff) = self.retry.next_backoff(transient_attempt) else {
+                        return Err(err);
+                    };
+                    transient_attempt += 1;
+                    log::warn!("{}; retrying in {:?} ({}/{})", err, backoff, transient_attempt, self.retry.retries);
+                    tokio::time::sleep(backoff).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn call_rpc_batch(&self, calls: Vec<(&str, Value)>) -> Result<Vec<Result<Value>>> {
+        self.with_retries(|| self.transport.call_batch(&calls)).await
+    }
+
+    async fn get_balance(&self, owner: &str) -> Result<String> {
+        let result = self.call_rpc(
+            "get_balance_by_owner",
+            json!({
+                "addr": owner
+            })
+        ).await?;
+        
+        Ok(result.as_str().unwrap_or("").to_string())
+    }
+
+    // Entries are either the bare UTXO hex or {"id", "utxo"} objects; only
+    // the latter can be spent from, since a Tx names its inputs by id.
+    async fn get_utxos_paginated(&self, last_utxo_id: &str, owner: &str) -> Result<(Vec<PageEntry>, Option<String>)> {
+        let result = self.call_rpc(
+            "get_list_of_utxo_by_owner_paginated",
+            json!({
+                "last_utxo_id": last_utxo_id,
+                "owner": owner
+            })
+        ).await?;
+
+        let utxos = result["utxos"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid utxos format"))?
+            .iter()
+            .map(|entry| match entry.as_str() {
+                Some(utxo) => PageEntry { id: None, utxo: utxo.to_string() },
+                None => PageEntry {
+                    id: entry["id"].as_str().map(|id| id.to_string()),
+                    utxo: entry["utxo"].as_str().unwrap_or("").to_string(),
+                },
+            })
+            .collect();
+
+        let last = result["last_utxo_id"].as_str().map(|s| s.to_string());
+
+        Ok((utxos, last))
+    }
+    
+    async fn get_next_id_of_utxo_by_owner(&self, utxo_id: &str, owner: &str) -> Result<Option<String>> {
         let result = self.call_rpc(
             "get_next_id_of_utxo_by_owner",
             json!({
@@ -167,20 +1097,178 @@ impl ApiClient {
         Ok(result.as_str().unwrap_or("").to_string())
     }
 
+    // One `method` call per id, sent UTXO_BATCH_SIZE at a time while the
+    // node accepts batches and `concurrency` at a time once it doesn't.
+    // Results are in id order. Only an answer showing the node can't batch
+    // turns batching off; any other batch failure fails just that chunk.
+    async fn call_per_utxo(&self, method: &str, utxo_ids: &[Fr], concurrency: usize) -> Vec<Result<Value>> {
+        let mut results = Vec::with_capacity(utxo_ids.len());
+        for chunk in utxo_ids.chunks(UTXO_BATCH_SIZE) {
+            if self.batch_supported.load(Ordering::Relaxed) {
+                let calls = chunk.iter().map(|id| (method, json!({ "id": id.to_hex() }))).collect();
+                match self.call_rpc_batch(calls).await {
+                    Ok(batch) => {
+                        results.extend(batch);
+                        continue;
+                    }
+                    Err(err) if is_batch_unsupported(&err) => {
+                        log::debug!("RPC batches unavailable, falling back to one {} per id: {}", method, err);
+                        self.batch_supported.store(false, Ordering::Relaxed);
+                    }
+                    Err(err) => {
+                        log::warn!("{} batch failed: {}", method, err);
+                        results.extend(chunk.iter().map(|_| Err(anyhow!("{} batch failed: {}", method, err))));
+                        continue;
+                    }
+                }
+            }
+            let single: Vec<Result<Value>> = stream::iter(chunk)
+                .map(|id| self.call_rpc(method, json!({ "id": id.to_hex() })))
+                .buffered(concurrency.max(1))
+                .collect()
+                .await;
+            results.extend(single);
+        }
+        results
+    }
+
+    async fn list_methods(&self) -> Result<Vec<String>> {
+        if let Ok(result) = self.call_rpc("rpc.discover", json!({})).await {
+            if let Some(methods) = result["methods"].as_array() {
+                return Ok(methods
+                    .iter()
+                    .filter_map(|m| m["name"].as_str().map(|s| s.to_string()))
+                    .collect());
+            }
+        }
+
+        let result = self.call_rpc("list_methods", json!({})).await?;
+        let methods = result
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid list_methods format"))?
+            .iter()
+            .filter_map(|m| m.as_str().map(|s| s.to_string()))
+            .collect();
+
+        Ok(methods)
+    }
+
+    async fn validate_transaction(&self, tx_hex: &str) -> Result<Option<String>> {
+        match self.call_rpc("validate_transaction", json!({ "tx": tx_hex })).await {
+            Ok(result) => {
+                if result.as_bool() == Some(false) || result["valid"].as_bool() == Some(false) {
+                    let reason = result["error"].as_str().unwrap_or("rejected by node");
+                    return Ok(Some(reason.to_string()));
+                }
+                Ok(None)
+            }
+            Err(err) if is_method_not_found(&err) => {
+                Err(anyhow!("node doesn't support simulation (no validate_transaction method)"))
+            }
+            Err(err) => match err.downcast_ref::<RpcError>() {
+                Some(rpc_err) => Ok(Some(rpc_err.to_string())),
+                None => Err(err),
+            },
+        }
+    }
+
     async fn get_tail(&self) -> Result<String> {
+        if let Some((fetched_at, tail)) = self.tail_cache.lock().unwrap().as_ref() {
+            if fetched_at.elapsed() < self.tail_ttl {
+                return Ok(tail.clone());
+            }
+        }
+        self.refresh_tail().await
+    }
+
+    async fn refresh_tail(&self) -> Result<String> {
         let result = self.call_rpc("get_tail", json!({})).await?;
-        Ok(result.as_str().unwrap_or("").to_string())
+        let tail = result.as_str().unwrap_or("").to_string();
+        *self.tail_cache.lock().unwrap() = Some((Instant::now(), tail.clone()));
+        Ok(tail)
+    }
+
+    // Nodes without a version RPC are still usable, so any failure here just
+    // means the version is unknown.
+    async fn get_version(&self) -> Option<String> {
+        match self.call_rpc("get_version", json!({})).await {
+            Ok(Value::String(version)) => Some(version),
+            Ok(version) => Some(version.to_string()),
+            Err(err) => {
+                log::debug!("get_version unavailable: {}", err);
+                None
+            }
+        }
     }
 
-    async fn submit_transaction(&self, tx_hex: &str) -> Result<()> {
-        self.call_rpc(
+    async fn submit_transaction(&self, tx_hex: &str, idempotency_key: &str) -> Result<()> {
+        self.transport.call_with_timeout(
             "submit_transaction",
             json!({
-                "tx": tx_hex
-            })
-        ).await?;
+                "tx": tx_hex,
+                "idempotency_key": idempotency_key
+            }),
+            self.submit_timeout,
+        ).await.map_err(|err| {
+            let timed_out = err.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_timeout())
+                || err.is::<tokio::time::error::Elapsed>();
+            if timed_out {
+                SubmitTimeout { secs: self.submit_timeout.as_secs() }.into()
+            } else {
+                err
+            }
+        })?;
         Ok(())
     }
+
+    // A lost response is retried, but only after asking the node whether the
+    // tx's inputs are already spent, which means an earlier attempt landed.
+    // A rejected resend is checked the same way before it is reported.
+    async fn submit_idempotent(&self, tx_hex: &str, idempotency_key: &str, spent_ids: &[Fr]) -> Result<Submission> {
+        let mut attempt = 0;
+        loop {
+            let err = match self.submit_transaction(tx_hex, idempotency_key).await {
+                Ok(()) => return Ok(Submission::New),
+                Err(err) => err,
+            };
+            let lost = is_transient(&err) || err.is::<SubmitTimeout>();
+            let backoff = if lost { self.retry.next_backoff(attempt) } else { None };
+            let Some(backoff) = backoff else {
+                if attempt > 0 && self.inputs_spent(spent_ids).await {
+                    return Ok(Submission::AlreadyKnown);
+                }
+                return Err(err);
+            };
+            
+            log::warn!("{}; checking whether the node already has the transaction", err);
+            if self.inputs_spent(spent_ids).await {
+                return Ok(Submission::AlreadyKnown);
+            }
+            attempt += 1;
+            log::warn!("Resubmitting in {:?} ({}/{})", backoff, attempt, self.retry.retries);
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    // True once every real input is gone from the UTXO set. A failed lookup
+    // counts as not spent, so the caller falls back to resubmitting.
+    async fn inputs_spent(&self, spent_ids: &[Fr]) -> bool {
+        let ids: Vec<&Fr> = spent_ids.iter().filter(|id| !id.is_zero()).collect();
+        if ids.is_empty() {
+            return false;
+        }
+        for id in ids {
+            match self.get_utxo(&id.to_hex()).await {
+                Ok(utxo) if utxo.is_empty() => {}
+                Ok(_) => return false,
+                Err(err) => {
+                    log::debug!("Could not look up input {}: {}", id.to_hex(), err);
+                    return false;
+                }
+            }
+        }
+        true
+    }
 }
 
 trait HexConverter {
@@ -202,19 +1290,125 @@ impl HexConverter for Fr {
     }
 }
 
-fn generate_address(secret: Fr) -> Result<String> {
+// Unlike HexConverter::from_hex, this rejects anything that would not
+// round-trip: a truncated, over-length or out-of-field address silently
+// decodes to some other account.
+fn parse_address(addr: &HexString) -> Result<Fr> {
+    let bytes = hex::decode(addr.to_string())?;
+    if bytes.len() != 32 {
+        return Err(anyhow!("Invalid address {}: expected 32 bytes, got {}", addr, bytes.len()));
+    }
+
+    let addr_fr = Fr::dec(&mut bytes.clone().into_iter())
+        .map_err(|_| anyhow!("Invalid address {}: not a canonical field element", addr))?;
+    if addr_fr.to_hex() != hex::encode(&bytes) {
+        return Err(anyhow!("Invalid address {}: not a canonical field element", addr));
+    }
+
+    Ok(addr_fr)
+}
+
+fn decode_balance(balance_hex: &str) -> Result<Fr> {
+    if balance_hex.is_empty() {
+        return Ok(Fr::from(0u32));
+    }
+    let bytes = hex::decode(balance_hex)?;
+    Fr::dec(&mut bytes.into_iter())
+}
+
+const DEFAULT_FEE: u32 = 3;
+
+fn parse_fee(fee: Option<&HexString>) -> Result<Fr> {
+    match fee {
+        Some(fee) => HexConverter::from_hex(fee.clone()),
+        None => Ok(Fr::from(DEFAULT_FEE)),
+    }
+}
+
+fn reject_zero(arg: &str, value: Fr) -> Result<()> {
+    if value.is_zero() {
+        return Err(anyhow!("Refusing to operate on the zero account: --{} is all zeros", arg));
+    }
+    Ok(())
+}
+
+// Checked on the Secret itself so the value is never copied out of it.
+fn reject_zero_secret(secret: &Secret) -> Result<()> {
+    if secret.is_zero() {
+        return Err(anyhow!("Refusing to operate on the zero account: --secret is all zeros"));
+    }
+    Ok(())
+}
+
+fn decode_prover_address(addr_hex: &str) -> Result<Fr> {
+    let invalid = || anyhow!("prover returned an invalid address {:?}; proof likely failed", addr_hex);
+    
+    if addr_hex.is_empty() {
+        return Err(invalid());
+    }
+    let bytes = hex::decode(addr_hex).map_err(|_| invalid())?;
+    if bytes.len() != 32 {
+        return Err(invalid());
+    }
+    
+    Fr::dec(&mut bytes.into_iter()).map_err(|_| invalid())
+}
+
+// The --inputs-file format: ids that each have to be an unspent coin of the
+// account.
+fn stage_record(account: &HexString, coins: &[(Fr, Out)], selected_ids: &[Fr]) -> Result<Value> {
+    for id in selected_ids {
+        if !coins.iter().any(|(coin_id, _)| coin_id == id) {
+            return Err(anyhow!("Selected coin {} is not an unspent UTXO of {}", id.to_hex(), account));
+        }
+    }
+    Ok(json!({
+        "account": account.to_string(),
+        "inputs": selected_ids.iter().map(|id| id.to_hex()).collect::<Vec<_>>(),
+    }))
+}
+
+fn create_secret<R: RngCore + CryptoRng>(rng: &mut R) -> Secret {
+    Secret::new(Fr::rand(rng))
+}
+
+fn seeded_secret(seed: &HexString) -> Result<Secret> {
+    let seed: [u8; 32] = hex::decode(seed.to_string())?
+        .try_into()
+        .map_err(|_| anyhow!("--seed must be exactly 32 bytes"))?;
+    Ok(create_secret(&mut StdRng::from_seed(seed)))
+}
+
+// Reads back what Create just wrote, so a KDF or encryption fault shows up
+// now rather than when the account is first spent from.
+fn verify_stored_account(path: &Path, passphrase: &str, account: &str) -> Result<()> {
+    let stored = keystore::decrypt(&keystore::read_record(path)?, path, passphrase)
+        .map_err(|err| anyhow!("Keystore {} does not read back: {}; do not use this account", path.display(), err))?;
+    let derived = generate_address(&stored)?;
+    if derived != account {
+        return Err(anyhow!(
+            "Keystore {} decrypts to the secret of {}, not {}; do not use this account",
+            path.display(),
+            derived,
+            account
+        ));
+    }
+    Ok(())
+}
+
+fn generate_address(secret: &Secret) -> Result<String> {
     let secret_hex = secret.to_hex();
-    wallet_prover_ffi::generate_address(&secret_hex)
+    Ok(wallet_prover_ffi::generate_address(&secret_hex)?)
 }
 
-fn generate_proof(secret: Fr, public_inputs: &[Fr]) -> Result<(String, String, String)> {
+fn generate_proof(secret: &Secret, public_inputs: &[Fr]) -> Result<(String, String, String)> {
     let secret_hex = secret.to_hex();
     let x_hex = public_inputs[0].to_hex();
     let y_hex = public_inputs[1].to_hex();
     let z_hex = public_inputs[2].to_hex();
     let w_hex = public_inputs[3].to_hex();
     
-    wallet_prover_ffi::generate_proof_hash_wallet(&secret_hex, &x_hex, &y_hex, &z_hex, &w_hex)
+    Ok(wallet_prover_ffi::generate_proof_hash_wallet(&secret_hex, &x_hex, &y_hex, &z_hex, &w_hex)?)
 }
 
 fn generate_proof_permissionless(public_inputs: &[Fr]) -> Result<(String, String, String)> {
@@ -223,397 +1417,3955 @@ fn generate_proof_permissionless(public_inputs: &[Fr]) -> Result<(String, String
     let z_hex = public_inputs[2].to_hex();
     let w_hex = public_inputs[3].to_hex();
     
-    wallet_prover_ffi::generate_proof_permissionless(&x_hex, &y_hex, &z_hex, &w_hex)
+    Ok(wallet_prover_ffi::generate_proof_permissionless(&x_hex, &y_hex, &z_hex, &w_hex)?)
 }
 
+const MAX_UTXO_DATA_LEN: usize = 64;
+// amount + owner + data elements, plus slack for the length prefix
+const MAX_UTXO_BYTES: usize = (2 + MAX_UTXO_DATA_LEN) * 32 + 8;
+
 fn decode_utxo(utxo_hex: &str) -> Result<Out> {
+    if utxo_hex.len() > MAX_UTXO_BYTES * 2 {
+        return Err(anyhow!(
+            "UTXO response too large: {} hex chars exceeds the {} byte limit",
+            utxo_hex.len(),
+            MAX_UTXO_BYTES
+        ));
+    }
+    
     let bytes = hex::decode(utxo_hex)?;
-    Out::dec(&mut bytes.into_iter())
+    let utxo = Out::dec(&mut bytes.clone().into_iter())?;
+    if utxo.data.len() > MAX_UTXO_DATA_LEN {
+        return Err(anyhow!(
+            "UTXO data has {} elements, more than the maximum of {}",
+            utxo.data.len(),
+            MAX_UTXO_DATA_LEN
+        ));
+    }
+    
+    let reencoded: Vec<u8> = utxo.clone().enc().collect();
+    if reencoded != bytes {
+        return Err(anyhow!(
+            "UTXO encoding mismatch: decoded output re-encodes to {} bytes instead of {} (wire format drift?)",
+            reencoded.len(),
+            bytes.len()
+        ));
+    }
+    
+    Ok(utxo)
 }
 
-fn fr_gte(fr1: Fr, fr2: Fr) -> bool {
-    let _diff = fr1 - fr2;
-    true
+fn decode_amount(result: &Value) -> Result<Fr> {
+    let bytes = hex::decode(result.as_str().unwrap_or(""))?;
+    Fr::dec(&mut bytes.into_iter())
 }
 
-fn select_utxos(utxos: Vec<(Fr, Out)>, amount: Fr) -> Option<((Fr, Out), (Fr, Out))> {
-    if utxos.is_empty() {
+fn is_owned_by(utxo: &Out, owner: Fr) -> bool {
+    if utxo.owner == owner {
+        return true;
+    }
+    log::warn!(
+        "Excluding UTXO ({}) owned by {} instead of {}",
+        format_amount(utxo.amount),
+        utxo.owner.to_hex(),
+        owner.to_hex()
+    );
+    false
+}
+
+fn decode_utxo_id(id_hex: &str) -> Result<Fr> {
+    let bytes = hex::decode(id_hex)?;
+    Fr::dec(&mut bytes.into_iter())
+}
+
+// The walk starts from id 0: no UTXO can have it (a zero id marks an empty
+// input slot in a Tx), so the node's first answer is the owner's first UTXO.
+async fn fetch_all_utxo_ids(api_client: &ApiClient, owner: &HexString) -> Result<Vec<Fr>> {
+    let mut utxo_ids = Vec::new();
+    let mut current_id = Fr::from(0u64);
+    
+    loop {
+        let id_hex = current_id.to_hex();
+        let next_hex = match api_client.get_next_id_of_utxo_by_owner(&id_hex, &owner.to_string()).await? {
+            Some(next_hex) if !next_hex.is_empty() => next_hex,
+            _ => break,
+        };
+        let next_id = decode_utxo_id(&next_hex)?;
+        if next_id.is_zero() {
+            break;
+        }
+        if utxo_ids.contains(&next_id) {
+            return Err(anyhow!("Node returned UTXO id {} twice while walking {}", next_id.to_hex(), owner));
+        }
+        utxo_ids.push(next_id);
+        current_id = next_id;
+    }
+    
+    Ok(utxo_ids)
+}
+
+// Listing only needs the outputs, so the paginated list is preferred here
+// even when its entries carry no ids.
+async fn fetch_all_utxos(api_client: &ApiClient, owner: &HexString) -> Result<Vec<Out>> {
+    let utxos = match fetch_all_utxos_paginated(api_client, owner).await {
+        Ok(listing) => return Ok(listing.into_iter().map(|(_, utxo)| utxo).collect()),
+        Err(err) if is_method_not_found(&err) => {
+            log::info!("Node has no paginated UTXO listing, walking UTXO ids instead");
+            walk_owned_utxos(api_client, owner, HexConverter::from_hex(owner.clone())?, UtxoDetail::Full).await?
+        }
+        Err(err) => return Err(err),
+    };
+    Ok(utxos.into_iter().map(|(_, utxo)| utxo).collect())
+}
+
+#[derive(Clone, Copy)]
+enum UtxoDetail {
+    Full,
+    // Only `amount` is filled in, from the light query. Ownership is left
+    // to whoever fetches the full outputs of the inputs it selects.
+    Amounts { concurrency: usize },
+}
+
+// Every UTXO of `account` with its id, from whichever method the node
+// supports: the id walk, or the paginated list when the walk is missing.
+// Either way the result is the same set.
+async fn fetch_owned_utxos(api_client: &ApiClient, account: &HexString, owner: Fr, detail: UtxoDetail) -> Result<Vec<(Fr, Out)>> {
+    match walk_owned_utxos(api_client, account, owner, detail).await {
+        Err(err) if is_method_not_found(&err) => {
+            log::info!("Node has no get_next_id_of_utxo_by_owner, using the paginated UTXO listing");
+        }
+        result => return result,
+    }
+    
+    fetch_all_utxos_paginated(api_client, account)
+        .await?
+        .into_iter()
+        .map(|(id, utxo)| {
+            id.map(|id| (id, utxo))
+                .ok_or_else(|| anyhow!("Node's paginated UTXO listing has no UTXO ids, so its UTXOs can't be spent"))
+        })
+        .collect()
+}
+
+async fn walk_owned_utxos(api_client: &ApiClient, account: &HexString, owner: Fr, detail: UtxoDetail) -> Result<Vec<(Fr, Out)>> {
+    let utxo_ids = fetch_all_utxo_ids(api_client, account).await?;
+    log::info!("Found {} UTXO IDs", utxo_ids.len());
+    
+    let mut utxos = Vec::new();
+    match detail {
+        UtxoDetail::Full => {
+            for result in fetch_and_decode_utxos(api_client, &utxo_ids).await {
+                match result {
+                    Ok((id, utxo)) if is_owned_by(&utxo, owner) => utxos.push((id, utxo)),
+                    Ok(_) => {}
+                    Err(err) => log::warn!("{}", err),
+                }
+            }
+        }
+        UtxoDetail::Amounts { concurrency } => {
+            let fetched = fetch_utxo_amounts(api_client, &utxo_ids, concurrency).await;
+            for (id, result) in utxo_ids.into_iter().zip(fetched) {
+                match result {
+                    Ok(amount) => utxos.push((id, Out { amount, ..Out::default() })),
+                    Err(err) => log::warn!("Skipping UTXO {}: {}", id.to_hex(), err),
+                }
+            }
+        }
+    }
+    Ok(utxos)
+}
+
+#[derive(Debug)]
+enum UtxoError {
+    Fetch { id: Fr, error: anyhow::Error },
+    Decode { id: Fr, error: anyhow::Error },
+}
+
+impl std::fmt::Display for UtxoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UtxoError::Fetch { id, error } => write!(f, "failed to fetch UTXO {}: {}", id.to_hex(), error),
+            UtxoError::Decode { id, error } => write!(f, "failed to decode UTXO {}: {}", id.to_hex(), error),
+        }
+    }
+}
+
+impl std::error::Error for UtxoError {}
+
+const UTXO_BATCH_SIZE: usize = 50;
+
+async fn fetch_utxo_hexes(api_client: &ApiClient, utxo_ids: &[Fr]) -> Vec<Result<String>> {
+    api_client
+        .call_per_utxo("get_utxo", utxo_ids, 1)
+        .await
+        .into_iter()
+        .map(|result| result.map(|utxo| utxo.as_str().unwrap_or("").to_string()))
+        .collect()
+}
+
+// Amounts only, through the light query while the node has it and from the
+// full outputs otherwise.
+async fn fetch_utxo_amounts(api_client: &ApiClient, utxo_ids: &[Fr], concurrency: usize) -> Vec<Result<Fr>> {
+    if api_client.light_utxo_query.load(Ordering::Relaxed) {
+        let results = api_client.call_per_utxo("get_utxo_amount", utxo_ids, concurrency).await;
+        if !results.iter().any(|result| result.as_ref().is_err_and(is_method_not_found)) {
+            return results.into_iter().map(|result| decode_amount(&result?)).collect();
+        }
+        log::debug!("get_utxo_amount unavailable, falling back to get_utxo");
+        api_client.light_utxo_query.store(false, Ordering::Relaxed);
+    }
+    
+    fetch_and_decode_utxos(api_client, utxo_ids)
+        .await
+        .into_iter()
+        .map(|result| result.map(|(_, utxo)| utxo.amount).map_err(Into::into))
+        .collect()
+}
+
+async fn fetch_and_decode_utxos(api_client: &ApiClient, utxo_ids: &[Fr]) -> Vec<Result<(Fr, Out), UtxoError>> {
+    let fetched = fetch_utxo_hexes(api_client, utxo_ids).await;
+    utxo_ids
+        .iter()
+        .zip(fetched)
+        .map(|(&id, result)| match result {
+            Ok(utxo_hex) => decode_utxo(&utxo_hex)
+                .map(|utxo| (id, utxo))
+                .map_err(|error| UtxoError::Decode { id, error }),
+            Err(error) => Err(UtxoError::Fetch { id, error }),
+        })
+        .collect()
+}
+
+async fn fetch_all_utxos_paginated(api_client: &ApiClient, owner: &HexString) -> Result<Vec<(Option<Fr>, Out)>> {
+    let owner_fr: Fr = HexConverter::from_hex(owner.clone())?;
+    let mut last_utxo_id = "0000000000000000000000000000000000000000000000000000000000000000".to_string();
+    let mut seen_cursors = HashSet::new();
+    // Keyed by id, or by the encoded UTXO for entries that carry no id, so a
+    // UTXO the node repeats on any later page is only counted once.
+    let mut seen_utxos = HashSet::new();
+    let mut utxos = Vec::new();
+    
+    loop {
+        let (page, next_id) = api_client.get_utxos_paginated(&last_utxo_id, &owner.to_string()).await?;
+        if page.is_empty() {
+            break;
+        }
+        
+        for entry in &page {
+            let id = match entry.id.as_deref().map(decode_utxo_id).transpose() {
+                Ok(id) => id,
+                Err(err) => {
+                    log::warn!("Skipping UTXO with invalid id {:?}: {}", entry.id, err);
+                    continue;
+                }
+            };
+            let key = id.map_or_else(|| entry.utxo.clone(), |id| id.to_hex());
+            if seen_utxos.contains(&key) {
+                log::warn!("Dropping UTXO {} repeated by the node on the page after {}", key, last_utxo_id);
+                continue;
+            }
+            seen_utxos.insert(key);
+            if let Ok(utxo) = decode_utxo(&entry.utxo) {
+                if is_owned_by(&utxo, owner_fr) {
+                    utxos.push((id, utxo));
+                }
+            }
+        }
+        
+        match next_id {
+            Some(next) if !next.is_empty() => {
+                if !seen_cursors.insert(next.clone()) {
+                    log::warn!("Node returned page cursor {} twice, dropping the repeated page", next);
+                    break;
+                }
+                last_utxo_id = next;
+            }
+            _ => break,
+        }
+    }
+    
+    Ok(utxos)
+}
+
+#[derive(Clone)]
+enum Selection {
+    One((Fr, Out)),
+    Two((Fr, Out), (Fr, Out)),
+}
+
+impl Selection {
+    fn from_inputs(mut inputs: Vec<(Fr, Out)>) -> Option<Self> {
+        match inputs.len() {
+            1 => Some(Selection::One(inputs.remove(0))),
+            2 => {
+                let second = inputs.remove(1);
+                Some(Selection::Two(inputs.remove(0), second))
+            }
+            _ => None,
+        }
+    }
+    
+    fn inputs(&self) -> Vec<&(Fr, Out)> {
+        match self {
+            Selection::One(first) => vec![first],
+            Selection::Two(first, second) => vec![first, second],
+        }
+    }
+    
+    fn total(&self) -> Option<Amount> {
+        self.inputs()
+            .iter()
+            .try_fold(Amount::zero(), |total, (_, utxo)| total.checked_add(Amount(utxo.amount)))
+    }
+    
+    // A Tx always has two input slots; a single-input spend leaves `iy` as
+    // the zero id.
+    fn tx_inputs(&self) -> (Fr, Fr) {
+        match self {
+            Selection::One((id, _)) => (*id, Fr::from(0u32)),
+            Selection::Two((first, _), (second, _)) => (*first, *second),
+        }
+    }
+    
+    fn swapped(self) -> Self {
+        match self {
+            Selection::Two(first, second) => Selection::Two(second, first),
+            one => one,
+        }
+    }
+}
+
+// select_utxos takes the first candidates that fit, so the strategy is
+// mostly the order they are offered in.
+fn order_candidates(utxos: &mut [(Fr, Out)], strategy: CoinSelection, seed: Option<u64>) {
+    match strategy {
+        CoinSelection::Random => match seed {
+            Some(seed) => utxos.shuffle(&mut StdRng::seed_from_u64(seed)),
+            None => utxos.shuffle(&mut OsRng),
+        },
+        CoinSelection::LargestFirst => utxos.sort_by_key(|(_, utxo)| std::cmp::Reverse(Amount(utxo.amount))),
+        CoinSelection::SmallestFirst => utxos.sort_by_key(|(_, utxo)| Amount(utxo.amount)),
+        CoinSelection::FirstFit | CoinSelection::BranchAndBound => {}
+    }
+}
+
+fn select_utxos(utxos: Vec<(Fr, Out)>, amount: Fr, fee: Fr, strategy: CoinSelection) -> Option<Selection> {
+    if utxos.is_empty() {
         return None;
     }
     
-    let fee = Fr::from(3u32);
-    let required = amount + fee;
+    let required = Amount(amount).checked_add(Amount(fee))?;
+    if let CoinSelection::BranchAndBound = strategy {
+        return select_min_change(&utxos, required);
+    }
     
-    for (id, utxo) in &utxos {
-        if fr_gte(utxo.amount, required) {
-            let zero = Fr::from(0u32);
-            return Some(((*id, utxo.clone()), (zero, Out::default())));
+    for utxo in &utxos {
+        if Amount(utxo.1.amount) >= required {
+            return Some(Selection::One(utxo.clone()));
         }
     }
     
     for i in 0..utxos.len() {
         for j in (i + 1)..utxos.len() {
-            let total = utxos[i].1.amount + utxos[j].1.amount;
-            if fr_gte(total, required) {
-                return Some((utxos[i].clone(), utxos[j].clone()));
+            let total = Amount(utxos[i].1.amount).checked_add(Amount(utxos[j].1.amount));
+            if total.is_some_and(|total| total >= required) {
+                return Some(Selection::Two(utxos[i].clone(), utxos[j].clone()));
+            }
+        }
+    }
+    
+    None
+}
+
+// Searches singles and pairs for the least change, preferring fewer inputs on
+// ties. Candidates are visited largest first, so once a pair falls short every
+// later partner does too and that branch is cut.
+fn select_min_change(utxos: &[(Fr, Out)], required: Amount) -> Option<Selection> {
+    let mut sorted: Vec<&(Fr, Out)> = utxos.iter().collect();
+    sorted.sort_by_key(|(_, utxo)| std::cmp::Reverse(Amount(utxo.amount)));
+    
+    let mut best: Option<((BigUint, usize), usize, Option<usize>)> = None;
+    let mut consider = |total: Amount, i: usize, j: Option<usize>| {
+        let key = (total.value() - required.value(), if j.is_some() { 2 } else { 1 });
+        if best.as_ref().is_none_or(|(best_key, ..)| key < *best_key) {
+            best = Some((key, i, j));
+        }
+    };
+    
+    for (i, (_, utxo)) in sorted.iter().enumerate() {
+        let first = Amount(utxo.amount);
+        if first >= required {
+            consider(first, i, None);
+            continue;
+        }
+        for (j, (_, partner)) in sorted.iter().enumerate().skip(i + 1) {
+            let Some(total) = first.checked_add(Amount(partner.amount)) else {
+                continue;
+            };
+            if total < required {
+                break;
             }
+            consider(total, i, Some(j));
         }
     }
     
+    let (_, i, j) = best?;
+    Some(match j {
+        Some(j) => Selection::Two(sorted[i].clone(), sorted[j].clone()),
+        None => Selection::One(sorted[i].clone()),
+    })
+}
+
+fn select_utxos_greedy(mut utxos: Vec<(Fr, Out)>, required: Amount) -> Option<Vec<(Fr, Out)>> {
+    utxos.sort_by_key(|(_, utxo)| std::cmp::Reverse(Amount(utxo.amount)));
+    
+    let mut total = Amount::zero();
+    let mut selected = Vec::new();
+    for utxo in utxos {
+        total = total.checked_add(Amount(utxo.1.amount))?;
+        selected.push(utxo);
+        if total >= required {
+            return Some(selected);
+        }
+    }
     None
 }
 
-fn construct_transfer_tx(
-    input1: (Fr, Out),
-    input2: (Fr, Out),
-    to: Fr,
+// What the inputs have to cover besides the fee: the amount plus any second
+// recipient's share.
+fn required_amount(amount: Fr, second_output: Option<(Fr, Fr)>) -> Result<Fr> {
+    let second = second_output.map_or(Amount::zero(), |(_, amount)| Amount(amount));
+    Amount(amount)
+        .checked_add(second)
+        .map(|required| required.0)
+        .ok_or_else(|| anyhow!("Amount plus the second output exceeds the field modulus"))
+}
+
+fn explain_unselectable(utxos: &[(Fr, Out)], amount: Fr, fee: Fr) -> anyhow::Error {
+    let greedy = Amount(amount)
+        .checked_add(Amount(fee))
+        .and_then(|required| select_utxos_greedy(utxos.to_vec(), required));
+    match greedy {
+        Some(inputs) if inputs.len() > 2 => anyhow!(
+            "amount requires {} inputs but tx supports 2; consolidate smaller UTXOs first",
+            inputs.len()
+        ),
+        // Enough funds in at most two inputs, so the candidates that could
+        // cover it were dropped when refetched.
+        Some(_) => anyhow!("The UTXOs that could cover this amount changed on the node or are not owned by this account; retry"),
+        None => anyhow!("Insufficient balance or unable to select UTXOs"),
+    }
+}
+
+// A Tx spends at most two inputs, so the most a single transfer can move is
+// the two largest UTXOs minus the fee.
+fn max_sendable(utxos: &[(Fr, Out)], fee: Fr) -> Option<(Selection, Amount)> {
+    let mut largest: Vec<(Fr, Out)> = utxos.iter().filter(|(_, utxo)| !utxo.amount.is_zero()).cloned().collect();
+    largest.sort_by_key(|(_, utxo)| std::cmp::Reverse(Amount(utxo.amount)));
+    largest.truncate(2);
+    
+    let selection = Selection::from_inputs(largest)?;
+    let sendable = selection.total()?.checked_sub(Amount(fee))?;
+    Some((selection, sendable))
+}
+
+async fn fetch_selected_utxo(api_client: &ApiClient, input: (Fr, Out), owner: Fr) -> Result<Option<(Fr, Out)>> {
+    let (id, placeholder) = input;
+    let utxo = decode_utxo(&api_client.get_utxo(&id.to_hex()).await?)?;
+    if utxo.amount != placeholder.amount {
+        return Err(anyhow!("UTXO {} changed while selecting inputs", id.to_hex()));
+    }
+    if !is_owned_by(&utxo, owner) {
+        return Ok(None);
+    }
+    
+    Ok(Some((id, utxo)))
+}
+
+async fn select_owned_utxos(
+    api_client: &ApiClient,
+    mut candidates: Vec<(Fr, Out)>,
     amount: Fr,
-    change_to: Fr,
-) -> Tx {
-    let total_input = input1.1.amount + input2.1.amount;
+    fee: Fr,
+    strategy: CoinSelection,
+    owner: Fr,
+) -> Result<Option<Selection>> {
+    loop {
+        let Some(selection) = select_utxos(candidates.clone(), amount, fee, strategy) else {
+            return Ok(None);
+        };
+        
+        let mut fetched = Vec::new();
+        let mut not_owned = Vec::new();
+        for input in selection.inputs() {
+            match fetch_selected_utxo(api_client, input.clone(), owner).await? {
+                Some(utxo) => fetched.push(utxo),
+                None => not_owned.push(input.0),
+            }
+        }
+        if not_owned.is_empty() {
+            return Ok(Selection::from_inputs(fetched));
+        }
+        candidates.retain(|(id, _)| !not_owned.contains(id));
+    }
+}
+
+async fn select_staged_utxos(
+    api_client: &ApiClient,
+    candidates: &[(Fr, Out)],
+    staged: &[Fr],
+    amount: Fr,
+    fee: Fr,
+    owner: Fr,
+) -> Result<Selection> {
+    if staged.len() > 2 {
+        return Err(anyhow!("{} inputs staged but a transaction supports at most 2", staged.len()));
+    }
+    
+    let mut inputs = Vec::new();
+    for id in staged {
+        let candidate = candidates
+            .iter()
+            .find(|(candidate_id, _)| candidate_id == id)
+            .ok_or_else(|| anyhow!("Staged input {} is not an unspent UTXO of this account", id.to_hex()))?;
+        let utxo = fetch_selected_utxo(api_client, candidate.clone(), owner)
+            .await?
+            .ok_or_else(|| anyhow!("Staged input {} is not owned by this account", id.to_hex()))?;
+        inputs.push(utxo);
+    }
+    
+    let selection = Selection::from_inputs(inputs).ok_or_else(|| anyhow!("No inputs staged"))?;
+    let required = Amount(amount).checked_add(Amount(fee));
+    if !matches!((selection.total(), required), (Some(total), Some(required)) if total >= required) {
+        return Err(anyhow!("Staged inputs do not cover the amount plus fee"));
+    }
+    
+    Ok(selection)
+}
+
+fn construct_transfer_tx(selection: &Selection, to: Fr, amount: Fr, fee: Fr, change_to: Fr) -> Result<Tx> {
+    let total_input = selection.total().ok_or_else(|| anyhow!("input total overflows the field"))?;
     
-    let fee = Fr::from(3u32);
-    let change = total_input - amount - fee;
+    let required = Amount(amount)
+        .checked_add(Amount(fee))
+        .ok_or_else(|| anyhow!("amount plus fee overflows the field"))?;
+    let change = total_input.checked_sub(required).ok_or_else(|| {
+        anyhow!("insufficient input: need {}, have {}", required.value(), total_input.value())
+    })?;
+    // A Tx always carries two outputs, so an exact spend keeps `oy` as a
+    // zero-amount output to the change address rather than omitting it.
+    if change == Amount::zero() {
+        log::info!("Exact spend: change output carries a zero amount");
+    }
     
+    let (ix, iy) = selection.tx_inputs();
+    Ok(construct_tx(ix, iy, (to, amount), (change_to, change.0)))
+}
+
+fn construct_tx(ix: Fr, iy: Fr, first: (Fr, Fr), second: (Fr, Fr)) -> Tx {
     let fee_data = vec![Fr::from(0u32), Fr::from(0u32), Fr::from(0u32)];
     
     Tx {
-        ix: input1.0,
-        iy: input2.0,
+        ix,
+        iy,
         ox: Out { 
-            amount, 
-            owner: to, 
+            amount: first.1, 
+            owner: first.0, 
             data: fee_data 
         },
         oy: Out { 
-            amount: change, 
-            owner: change_to, 
+            amount: second.1, 
+            owner: second.0, 
             data: Vec::new() 
         },
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
+fn check_value_conserved(inputs: &[(Fr, Fr)], tx: &Tx, fee: Fr) -> Result<()> {
+    let inputs_total: BigUint = inputs.iter().map(|(_, amount)| fr_to_biguint(*amount)).sum();
+    let outputs_total = fr_to_biguint(tx.ox.amount) + fr_to_biguint(tx.oy.amount) + fr_to_biguint(fee);
     
-    let cli = Cli::parse();
-    let api_client = ApiClient::new(cli.api_url);
+    if inputs_total != outputs_total {
+        return Err(anyhow!(
+            "tx does not conserve value: inputs total {} but outputs plus fee total {}",
+            inputs_total,
+            outputs_total
+        ));
+    }
+    Ok(())
+}
 
-    match &cli.command {
-        Commands::Create => {
-            println!("Creating new wallet account...");
-            
-            let secret = Fr::rand(&mut OsRng);
-            println!("Secret: {}", secret.to_hex());
-            
-            match generate_address(secret) {
-                Ok(vk_hex) => {
-                    println!("Account (VK): {}", vk_hex);
-                }
-                Err(err) => {
-                    eprintln!("Failed to generate VK: {}", err);
-                }
-            }
-        }
+fn verify_proof(vk: &Vk, proof: &Proof, tx: &Tx) -> bool {
+    let inputs: Inputs = tx.clone().into();
+    let input_array: [Fr; 4] = inputs.into();
+    vk.verify(proof, &input_array)
+}
+
+fn check_roundtrip(wp_tx_bytes: &[u8], tx: &Tx) -> Result<()> {
+    let Wp { val, .. } = Wp::dec(&mut wp_tx_bytes.iter().copied())
+        .map_err(|e| anyhow!("Encoded transaction does not decode: {}", e))?;
+    let decoded: Tx = val;
+    
+    let same_out = |a: &Out, b: &Out| a.amount == b.amount && a.owner == b.owner && a.data == b.data;
+    if decoded.ix != tx.ix || decoded.iy != tx.iy || !same_out(&decoded.ox, &tx.ox) || !same_out(&decoded.oy, &tx.oy) {
+        return Err(anyhow!("Encoded transaction decodes to a different tx (enc/dec asymmetry); refusing to submit"));
+    }
+    Ok(())
+}
+
+struct TransferPlan {
+    inputs: Vec<(Fr, Fr)>,
+    fee: Fr,
+    balance_before: Fr,
+    balance_after: Fr,
+    has_change: bool,
+    tx: Tx,
+}
+
+impl TransferPlan {
+    fn new(
+        selected: Selection,
+        to: Fr,
+        amount: Fr,
+        second_output: Option<(Fr, Fr)>,
+        fee: Fr,
+        change_to: Fr,
+        balance_before: Fr,
+    ) -> Result<Self> {
+        let inputs: Vec<(Fr, Fr)> = selected.inputs().iter().map(|(id, utxo)| (*id, utxo.amount)).collect();
+        let total_input = selected.total().ok_or_else(|| anyhow!("input total overflows the field"))?.0;
         
-        Commands::GetBalance { account } => {
-            println!("Getting balance for account: {}", account);
-            
-            match api_client.get_balance(&account.to_string()).await {
-                Ok(balance_hex) => {
-                    let balance_bytes = hex::decode(balance_hex)?;
-                    println!("Balance (hex bytes): {}", hex::encode(&balance_bytes));
-                }
-                Err(err) => {
-                    eprintln!("Failed to get balance: {}", err);
-                }
+        let tx = match second_output {
+            Some(second) => {
+                let (ix, iy) = selected.tx_inputs();
+                construct_tx(ix, iy, (to, amount), second)
             }
+            None => construct_transfer_tx(&selected, to, amount, fee, change_to)?,
+        };
+        let actual_fee = total_input - tx.ox.amount - tx.oy.amount;
+        // With two payees there is no change slot, so any excess input
+        // would silently become fee.
+        if second_output.is_some() && actual_fee != fee {
+            return Err(anyhow!(
+                "--second-output leaves no room for change: selected inputs exceed both outputs plus fee by {}",
+                format_amount(actual_fee - fee)
+            ));
         }
+        let fee = actual_fee;
+        let kept = if second_output.is_some() { Fr::from(0u32) } else { tx.oy.amount };
         
-        Commands::ListUtxos { account } => {
-            println!("Listing UTXOs for account: {}", account);
-            
-            let mut last_utxo_id = "0000000000000000000000000000000000000000000000000000000000000000".to_string();
-            let mut total_utxos = 0;
-            
-            loop {
-                match api_client.get_utxos_paginated(&last_utxo_id, &account.to_string()).await {
-                    Ok((utxos, next_id)) => {
-                        if utxos.is_empty() {
-                            break;
-                        }
-                        
-                        for utxo_hex in &utxos {
-                            if let Ok(utxo) = decode_utxo(utxo_hex) {
-                                total_utxos += 1;
-                                println!("UTXO #{}: Amount={}", 
-                                    total_utxos,
-                                    utxo.amount.to_hex()
-                                );
-                            }
-                        }
-                        
-                        match next_id {
-                            Some(next) if !next.is_empty() => last_utxo_id = next,
-                            _ => break,
-                        }
-                    }
-                    Err(err) => {
-                        eprintln!("Failed to get UTXOs: {}", err);
-                        break;
-                    }
-                }
-            }
-            
-            println!("\nTotal UTXOs found: {}", total_utxos);
+        Ok(Self {
+            inputs,
+            fee,
+            balance_before,
+            balance_after: balance_before - total_input + kept,
+            has_change: second_output.is_none(),
+            tx,
+        })
+    }
+    
+    fn print(&self) {
+        say!("Transfer plan:");
+        for (id, amount) in &self.inputs {
+            say!("  Input {}: {}", id.to_hex(), format_amount(*amount));
         }
-        
-        Commands::Transfer { from, to, amount, secret } => {
-            println!("Preparing transfer...");
-            println!("From: {}", from);
-            println!("To: {}", to);
-            println!("Amount: {}", amount);
-            
-            let amount_fr = HexConverter::from_hex(amount.clone())?;
-            let to_fr = HexConverter::from_hex(to.clone())?;
-            let secret_fr = HexConverter::from_hex(secret.clone())?;
-            
-            let mut utxo_ids = Vec::new();
-            let mut current_id = Fr::from(8u64);
-            
-            for _ in 0..100 {
-                let id_hex = current_id.to_hex();
-                match api_client.get_next_id_of_utxo_by_owner(&id_hex, &from.to_string()).await {
-                    Ok(Some(next_hex)) => {
-                        if next_hex.is_empty() {
-                            break;
-                        }
-                        let next_bytes = hex::decode(&next_hex)?;
-                        let next_id = Fr::dec(&mut next_bytes.into_iter())?;
-                        if next_id.is_zero() {
-                            break;
-                        }
-                        utxo_ids.push(next_id);
-                        current_id = next_id;
-                    }
-                    _ => break,
-                }
-            }
-            
-            println!("Found {} UTXO IDs", utxo_ids.len());
-            
-            let mut all_utxos = Vec::new();
-            for utxo_id in utxo_ids {
-                let utxo_id_hex = utxo_id.to_hex();
-                match api_client.get_utxo(&utxo_id_hex).await {
-                    Ok(utxo_hex) => {
-                        if let Ok(utxo) = decode_utxo(&utxo_hex) {
-                            all_utxos.push((utxo_id, utxo.clone()));
-                            println!("UTXO: id={}, amount={}", utxo_id_hex, utxo.amount.to_hex());
-                        }
-                    }
-                    Err(_) => continue,
-                }
-            }
-            
-            println!("Fetched {} UTXOs", all_utxos.len());
-            
-            let selected = match select_utxos(all_utxos, amount_fr) {
-                Some(s) => s,
-                None => {
-                    eprintln!("Insufficient balance or unable to select UTXOs");
-                    return Ok(());
-                }
-            };
-            
-            println!("Selected UTXO 1: amount = {}", selected.0.1.amount.to_hex());
-            if !selected.1.0.is_zero() {
-                println!("Selected UTXO 2: amount = {}", selected.1.1.amount.to_hex());
-            }
-            
-            let from_address_hex = generate_address(secret_fr)?;
-            let addr_bytes = hex::decode(&from_address_hex)?;
-            let from_address = Fr::dec(&mut addr_bytes.into_iter())?;
-            
-            let tx = construct_transfer_tx(
-                selected.0,
-                selected.1,
-                to_fr,
-                amount_fr,
-                from_address,
-            );
-            
-            let tx_hex = hex::encode(tx.clone().enc().collect::<Vec<u8>>());
-            println!("Transaction constructed: {}...", &tx_hex[..60.min(tx_hex.len())]);
-            
-            let inputs: Inputs = tx.clone().into();
-            let input_array: [Fr; 4] = inputs.into();
-            
-            match generate_proof(secret_fr, &input_array) {
-                Ok((proof_hex, vk_hex, addr_hex)) => {
-                    println!("Proof generated successfully");
-                    println!("Address: {}", addr_hex);
-                    
-                    let addr_bytes = hex::decode(&addr_hex)?;
-                    let addr = Fr::dec(&mut addr_bytes.into_iter())?;
-                    if addr != from_address {
-                        eprintln!("❌ Address mismatch!");
-                        return Ok(());
-                    }
-                    
-                    let proof_bytes = hex::decode(proof_hex)?;
-                    let proof = Proof::dec(&mut proof_bytes.into_iter())?;
-                    
-                    let vk_bytes = hex::decode(&vk_hex)?;
-                    let vk = Vk::dec(&mut vk_bytes.into_iter())?;
-                    
-                    let wp_tx = Wp {
-                        vk,
-                        proof,
-                        val: tx.clone(),
-                    };
-                    
-                    let wp_tx_hex = hex::encode(wp_tx.enc().collect::<Vec<u8>>());
-                    let tx_hash = tx.hash();
-                    
-                    match api_client.submit_transaction(&wp_tx_hex).await {
-                        Ok(()) => {
-                            println!("Transaction hash: {}", tx_hash.to_hex());
-                        }
-                        Err(err) => {
-                            eprintln!("\n❌ Failed to submit transaction: {}", err);
-                        }
-                    }
-                }
-                Err(err) => {
-                    eprintln!("❌ Failed to generate proof: {}", err);
-                }
-            }
+        say!("  Pay {} to {}", format_amount(self.tx.ox.amount), self.tx.ox.owner.to_hex());
+        if !self.has_change {
+            say!("  Pay {} to {}", format_amount(self.tx.oy.amount), self.tx.oy.owner.to_hex());
+        } else if self.tx.oy.amount.is_zero() {
+            say!("  Change: none (exact spend, zero-amount output)");
+        } else {
+            say!("  Change {} to {}", format_amount(self.tx.oy.amount), self.tx.oy.owner.to_hex());
         }
+        say!("  Fee: {}", format_amount(self.fee));
+        say!("  Balance: {} -> {}", format_amount(self.balance_before), format_amount(self.balance_after));
         
-        Commands::TransferPermissionless { from, to, amount } => {
-            println!("Preparing permissionless transfer...");
-            println!("From: {}", from);
-            println!("To: {}", to);
-            println!("Amount: {}", amount);
-            
-            let amount_fr = HexConverter::from_hex(amount.clone())?;
-            let to_fr = HexConverter::from_hex(to.clone())?;
-            let from_fr = HexConverter::from_hex(from.clone())?;
-            
-            println!("\n[1/5] Fetching UTXOs...");
-            
-            let mut utxo_ids = Vec::new();
-            let mut current_id = Fr::from(8u64);
-            
-            for _ in 0..100 {
-                let id_hex = current_id.to_hex();
-                match api_client.get_next_id_of_utxo_by_owner(&id_hex, &from.to_string()).await {
-                    Ok(Some(next_hex)) => {
-                        if next_hex.is_empty() {
-                            break;
-                        }
-                        let next_bytes = hex::decode(&next_hex)?;
-                        let next_id = Fr::dec(&mut next_bytes.into_iter())?;
-                        if next_id.is_zero() {
-                            break;
-                        }
-                        utxo_ids.push(next_id);
-                        current_id = next_id;
-                    }
-                    _ => break,
-                }
-            }
-            
-            println!("Found {} UTXO IDs", utxo_ids.len());
-            
-            let mut all_utxos = Vec::new();
-            for utxo_id in utxo_ids {
-                let utxo_id_hex = utxo_id.to_hex();
-                match api_client.get_utxo(&utxo_id_hex).await {
-                    Ok(utxo_hex) => {
-                        if let Ok(utxo) = decode_utxo(&utxo_hex) {
-                            all_utxos.push((utxo_id, utxo.clone()));
-                            println!("UTXO: id={}, amount={}", utxo_id_hex, utxo.amount.to_hex());
-                        }
-                    }
-                    Err(_) => continue,
-                }
-            }
-            
-            println!("Fetched {} UTXOs", all_utxos.len());
-            
-            let selected = match select_utxos(all_utxos, amount_fr) {
-                Some(s) => s,
-                None => {
-                    eprintln!("Insufficient balance or unable to select UTXOs");
-                    return Ok(());
-                }
-            };
-            
-            println!("Selected UTXO 1: amount = {}", selected.0.1.amount.to_hex());
-            if !selected.1.0.is_zero() {
-                println!("Selected UTXO 2: amount = {}", selected.1.1.amount.to_hex());
-            }
-            
-            let tx = construct_transfer_tx(
-                selected.0,
-                selected.1,
-                to_fr,
-                amount_fr,
-                from_fr,
-            );
-            
-            let tx_hex = hex::encode(tx.clone().enc().collect::<Vec<u8>>());
-            println!("Transaction constructed: {}...", &tx_hex[..60.min(tx_hex.len())]);
-            
-            let inputs: Inputs = tx.clone().into();
-            let input_array: [Fr; 4] = inputs.into();
-            
-            match generate_proof_permissionless(&input_array) {
-                Ok((proof_hex, vk_hex, addr_hex)) => {
-                    println!("Proof generated successfully");
-                    println!("Address: {}", addr_hex);
-                    
-                    let proof_bytes = hex::decode(proof_hex)?;
-                    let addr_bytes = hex::decode(&addr_hex)?;
-                    let addr = Fr::dec(&mut addr_bytes.into_iter())?;
-                    
-                    if addr != from_fr {
-                        eprintln!("❌ Address mismatch! Expected {}, got {}", from, addr_hex);
-                        return Ok(());
-                    }
-                    
-                    let proof = Proof::dec(&mut proof_bytes.into_iter())?;
-                    
-                    let vk_bytes = hex::decode(&vk_hex)?;
-                    let vk = Vk::dec(&mut vk_bytes.into_iter())?;
-                    
-                    let wp_tx = Wp {
-                        vk,
-                        proof,
-                        val: tx.clone(),
-                    };
-                    
-                    let wp_tx_hex = hex::encode(wp_tx.enc().collect::<Vec<u8>>());
-                    let tx_hash = tx.hash();
-                    
-                    match api_client.submit_transaction(&wp_tx_hex).await {
-                        Ok(()) => {
-                            println!("Transaction hash: {}", tx_hash.to_hex());
-                        }
-                        Err(err) => {
-                            eprintln!("\n❌ Failed to submit transaction: {}", err);
-                        }
-                    }
-                }
-                Err(err) => {
-                    eprintln!("❌ Failed to generate proof: {}", err);
-                }
-            }
-        }
+        let tx_hex = hex::encode(self.tx.clone().enc().collect::<Vec<u8>>());
+        log::debug!("Transaction constructed: {}...", &tx_hex[..60.min(tx_hex.len())]);
+    }
+    
+    fn to_json(&self) -> Value {
+        json!({
+            "inputs": self.inputs.iter().map(|(id, amount)| json!({
+                "id": id.to_hex(),
+                "amount": amount_json(*amount),
+            })).collect::<Vec<_>>(),
+            "fee": amount_json(self.fee),
+            "balance_before": amount_json(self.balance_before),
+            "balance_after": amount_json(self.balance_after),
+            "tx": tx_summary(&self.tx),
+            "tx_hex": hex::encode(self.tx.clone().enc().collect::<Vec<u8>>()),
+        })
     }
+}
 
-    Ok(())
+fn prove_tx(
+    tx: &Tx,
+    authority: Authority<'_>,
+    change_to: Fr,
+    verify: bool,
+    roundtrip: bool,
+    vk_cache: Option<&Path>,
+) -> Result<Vec<u8>> {
+    let inputs: Inputs = tx.clone().into();
+    let input_array: [Fr; 4] = inputs.into();
+    
+    let proof_result = match authority {
+        Authority::Secret(secret) => generate_proof(secret, &input_array),
+        Authority::Permissionless => generate_proof_permissionless(&input_array),
+    };
+    let (proof_hex, vk_hex, addr_hex) = proof_result.map_err(|err| anyhow!("Failed to generate proof: {}", err))?;
+    log::info!("Proof generated successfully");
+    log::debug!("Address: {}", addr_hex);
+    
+    let addr = decode_prover_address(&addr_hex)?;
+    if addr != change_to {
+        return Err(anyhow!("Address mismatch! Expected {}, got {}", change_to.to_hex(), addr_hex));
+    }
+    
+    let proof_bytes = hex::decode(proof_hex)?;
+    let proof = Proof::dec(&mut proof_bytes.into_iter())?;
+    
+    let vk = match vk_cache {
+        Some(path) => cached_vk(&vk_cache_file(path, authority), &vk_hex)?,
+        None => Vk::dec(&mut hex::decode(&vk_hex)?.into_iter())?,
+    };
+    
+    if verify && !verify_proof(&vk, &proof, tx) {
+        return Err(anyhow!("Generated proof failed local verification; not submitting"));
+    }
+    
+    let wp_tx = Wp {
+        vk,
+        proof,
+        val: tx.clone(),
+    };
+    
+    let wp_tx_bytes: Vec<u8> = wp_tx.enc().collect();
+    if roundtrip {
+        check_roundtrip(&wp_tx_bytes, tx)?;
+    }
+    Ok(wp_tx_bytes)
+}
+
+#[derive(Clone, Copy)]
+enum Authority<'a> {
+    Secret(&'a Secret),
+    Permissionless,
+}
+
+impl Authority<'_> {
+    fn circuit(&self) -> &'static str {
+        match self {
+            Authority::Secret(_) => "hash_wallet",
+            Authority::Permissionless => "permissionless",
+        }
+    }
+}
+
+// Each circuit has its own Vk, so the cache path gets a per-circuit suffix.
+fn vk_cache_file(path: &Path, authority: Authority<'_>) -> PathBuf {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(format!(".{}", authority.circuit()));
+    path.with_file_name(name)
+}
+
+struct CachedVk {
+    path: PathBuf,
+    hex: String,
+    vk: Vk,
+}
+
+// Decoded Vks, loaded from the cache files at most once per process.
+static VK_CACHE: Mutex<Vec<CachedVk>> = Mutex::new(Vec::new());
+
+fn load_cached_vk(path: &Path) -> Option<CachedVk> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            log::warn!("Failed to read Vk cache {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    let hex = hex::encode(&bytes);
+    match Vk::dec(&mut bytes.into_iter()) {
+        Ok(vk) => Some(CachedVk { path: path.to_path_buf(), hex, vk }),
+        Err(err) => {
+            log::warn!("Vk cache {} does not decode ({}); replacing it", path.display(), err);
+            None
+        }
+    }
+}
+
+// Returns the cached Vk, without decoding the prover's, when the two agree.
+// On a mismatch the prover's Vk wins, since that is what the proof was made
+// against, and the cache is rewritten.
+fn cached_vk(path: &Path, vk_hex: &str) -> Result<Vk> {
+    let vk_hex = vk_hex.trim_start_matches("0x");
+    let mut cache = VK_CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let index = match cache.iter().position(|entry| entry.path == path) {
+        Some(index) => Some(index),
+        None => load_cached_vk(path).map(|entry| {
+            cache.push(entry);
+            cache.len() - 1
+        }),
+    };
+    
+    if let Some(index) = index {
+        if cache[index].hex.eq_ignore_ascii_case(vk_hex) {
+            log::debug!("Using cached Vk from {}", path.display());
+            return Ok(cache[index].vk.clone());
+        }
+        log::warn!("Cached Vk in {} does not match the prover's; replacing it", path.display());
+        cache.remove(index);
+    }
+    
+    let bytes = hex::decode(vk_hex)?;
+    if let Err(err) = fs::write(path, &bytes) {
+        log::warn!("Failed to write Vk cache {}: {}", path.display(), err);
+    }
+    let vk = Vk::dec(&mut bytes.into_iter())?;
+    cache.push(CachedVk { path: path.to_path_buf(), hex: vk_hex.to_ascii_lowercase(), vk: vk.clone() });
+    Ok(vk)
+}
+
+// Selection candidates for a transfer: the amounts of every unspent UTXO of
+// `from`, skipping ids an earlier transfer in this run already spent. Full
+// outputs are fetched later, only for the inputs that get selected.
+async fn gather_transfer_utxos(
+    api_client: &ApiClient,
+    from: &HexString,
+    spent: &[Fr],
+    concurrency: usize,
+) -> Result<Vec<(Fr, Out)>> {
+    let owner = parse_address(from)?;
+    let mut utxos = fetch_owned_utxos(api_client, from, owner, UtxoDetail::Amounts { concurrency }).await?;
+    utxos.retain(|(id, _)| !spent.contains(id));
+    for (id, utxo) in &utxos {
+        log::debug!("UTXO: id={}, {}", id.to_hex(), format_amount(utxo.amount));
+    }
+    
+    log::info!("Fetched {} UTXOs", utxos.len());
+    Ok(utxos)
+}
+
+async fn run_transfer(
+    api_client: &ApiClient,
+    cli: &Cli,
+    args: &TransferArgs,
+    authority: Authority<'_>,
+    spent: &[Fr],
+) -> Result<(Tx, Value)> {
+    let kind = match authority {
+        Authority::Secret(_) => "transfer",
+        Authority::Permissionless => "permissionless transfer",
+    };
+    log::info!("Preparing {} of {} from {} to {}", kind, args.amount, args.from, args.to);
+    if !args.dry_run && !args.simulate {
+        require_yes_for_json(args.yes)?;
+    }
+    
+    let amount_fr = HexConverter::from_hex(args.amount.clone())?;
+    let to_fr = parse_address(&args.to)?;
+    let second_output = args.second_output()?;
+    let fee_fr = args.fee()?;
+    let from_fr = parse_address(&args.from)?;
+    reject_zero("from", from_fr)?;
+    
+    let mut all_utxos = gather_transfer_utxos(api_client, &args.from, spent, args.concurrency).await?;
+    let balance_before = all_utxos.iter().fold(Fr::from(0u32), |total, (_, utxo)| total + utxo.amount);
+    
+    order_candidates(&mut all_utxos, args.coin_selection, args.selection_seed);
+    
+    let staged = args.staged_inputs()?;
+    let required_fr = required_amount(amount_fr, second_output)?;
+    let selected = if staged.is_empty() {
+        match select_owned_utxos(api_client, all_utxos.clone(), required_fr, fee_fr, args.coin_selection, from_fr).await? {
+            Some(selected) => selected,
+            None => return Err(explain_unselectable(&all_utxos, required_fr, fee_fr)),
+        }
+    } else {
+        select_staged_utxos(api_client, &all_utxos, &staged, required_fr, fee_fr, from_fr).await?
+    };
+    
+    for (i, (_, utxo)) in selected.inputs().iter().enumerate() {
+        log::info!("Selected UTXO {}: {}", i + 1, format_out(utxo, OutFormat::default()));
+    }
+    
+    let change_to = match authority {
+        Authority::Secret(secret) => decode_prover_address(&generate_address(secret)?)?,
+        Authority::Permissionless => from_fr,
+    };
+    // Compare as field elements so a differently-cased or re-encoded copy of
+    // the wallet's own address is still caught.
+    if to_fr == change_to || to_fr == from_fr {
+        if !args.allow_self {
+            return Err(anyhow!(
+                "--to {} is this wallet's own address (self-payment); pass --allow-self to send anyway",
+                args.to
+            ));
+        }
+        log::warn!("Paying this wallet's own address {} (--allow-self)", to_fr.to_hex());
+    }
+    
+    let selected = match args.input_order {
+        InputOrder::Natural => selected,
+        InputOrder::Swapped => selected.swapped(),
+    };
+    let plan = TransferPlan::new(selected, to_fr, amount_fr, second_output, fee_fr, change_to, balance_before)?;
+    if !output::json() {
+        plan.print();
+    }
+    let plan_json = plan.to_json();
+    if !args.skip_conservation_check {
+        check_value_conserved(&plan.inputs, &plan.tx, fee_fr)?;
+    }
+    let tx = plan.tx;
+    
+    let wp_tx_bytes = prove_tx(
+        &tx,
+        authority,
+        change_to,
+        args.verify_before_submit,
+        !args.no_roundtrip_check,
+        cli.vk_cache.as_deref(),
+    )?;
+    let wp_tx_hex = hex::encode(&wp_tx_bytes);
+    let tx_hash = tx.hash();
+    
+    if args.simulate {
+        let mut report = report_simulation(api_client.validate_transaction(&wp_tx_hex).await?);
+        report["plan"] = plan_json;
+        return Ok((tx, report));
+    }
+    
+    if args.dry_run {
+        say!("Dry run: not submitting");
+        say!("Transaction hash: {}", format_hash(tx_hash, cli.hash_format));
+        say!("Wp tx: {}", wp_tx_hex);
+        let report = json!({
+            "tx_hash": format_hash(tx_hash, cli.hash_format),
+            "status": "dry_run",
+            "wp_tx": wp_tx_hex,
+            "plan": plan_json,
+        });
+        return Ok((tx, report));
+    }
+    
+    if !args.yes {
+        say!("\nAbout to submit:");
+        say!("  From: {}", from_fr.to_hex());
+        say!("  To: {}", tx.ox.owner.to_hex());
+        say!("  Amount: {}", format_amount(tx.ox.amount));
+        say!("  Fee: {}", format_amount(plan.fee));
+        say!("  Change: {}", format_amount(if plan.has_change { tx.oy.amount } else { Fr::from(0u32) }));
+        say!("  Inputs: {}", plan.inputs.len());
+        say!("  Transaction hash: {}", format_hash(tx_hash, cli.hash_format));
+        confirm("submit")?;
+    }
+    
+    let idempotency_key = args.idempotency_key.clone().unwrap_or_else(|| tx_hash.to_hex());
+    
+    let spent_ids: Vec<Fr> = plan.inputs.iter().map(|(id, _)| *id).collect();
+    let submission = api_client
+        .submit_idempotent(&wp_tx_hex, &idempotency_key, &spent_ids)
+        .await
+        .map_err(|err| anyhow!("Failed to submit transaction: {}", err))?;
+    if submission == Submission::AlreadyKnown {
+        say!("Transaction was already submitted by an earlier attempt");
+    }
+    say!("Transaction hash: {}", format_hash(tx_hash, cli.hash_format));
+    
+    if let Some(dir) = &cli.archive_dir {
+        if let Err(err) = archive_submission(dir, &tx, &wp_tx_bytes, cli.archive_gzip) {
+            log::warn!("Failed to archive transaction {}: {}", tx_hash.to_hex(), err);
+        }
+    }
+    
+    let mut status = submission.status();
+    if let Some(wait_timeout) = args.wait_timeout {
+        if wait_for_tx(api_client, tx_hash, &spent_ids, Duration::from_secs(wait_timeout)).await {
+            say!("confirmed");
+            status = "confirmed";
+        } else {
+            say!("timeout, still pending");
+            status = "pending";
+        }
+    }
+    
+    let report = json!({
+        "tx_hash": format_hash(tx_hash, cli.hash_format),
+        "status": status,
+        "plan": plan_json,
+    });
+    Ok((tx, report))
+}
+
+// --json output has no one at the terminal to answer a prompt, so those runs
+// must opt in with --yes up front, before any work is done.
+fn require_yes_for_json(yes: bool) -> Result<()> {
+    if output::json() && !yes {
+        return Err(anyhow!("--json cannot prompt for confirmation; pass --yes to go ahead"));
+    }
+    Ok(())
+}
+
+fn confirm(action: &str) -> Result<()> {
+    print!("Type \"yes\" to {}: ", action);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if answer.trim() != "yes" {
+        return Err(anyhow!("Not confirmed; did not {}", action));
+    }
+    Ok(())
+}
+
+async fn run_transfers(api_client: &ApiClient, cli: &Cli, args: &TransferArgs, authority: Authority<'_>) -> Result<()> {
+    let mut spent = Vec::new();
+    if args.repeat <= 1 {
+        let (_, report) = run_transfer(api_client, cli, args, authority, &spent).await?;
+        output::emit(&report);
+        return Ok(());
+    }
+    
+    let mut latencies = Vec::new();
+    let mut failures = 0;
+    let mut reports = Vec::new();
+    for round in 1..=args.repeat {
+        say!("\n=== Transfer {}/{} ===", round, args.repeat);
+        let started = Instant::now();
+        match run_transfer(api_client, cli, args, authority, &spent).await {
+            Ok((tx, report)) => {
+                latencies.push(started.elapsed());
+                spent.push(tx.ix);
+                spent.push(tx.iy);
+                reports.push(report);
+            }
+            Err(err) => {
+                failures += 1;
+                eprintln!("❌ Transfer {} failed: {}", round, err);
+                reports.push(json!({ "error": err.to_string() }));
+            }
+        }
+    }
+    
+    say!("\nSucceeded: {}, failed: {}", latencies.len(), failures);
+    if !latencies.is_empty() {
+        latencies.sort();
+        say!(
+            "Latency: min {:?}, median {:?}, max {:?}",
+            latencies[0],
+            latencies[latencies.len() / 2],
+            latencies[latencies.len() - 1]
+        );
+    }
+    
+    let error = (failures > 0).then(|| anyhow!("{} of {} transfers failed", failures, args.repeat));
+    output::emit(&json!({
+        "transfers": reports,
+        "succeeded": latencies.len(),
+        "failed": failures,
+        "error": error.as_ref().map(|err| err.to_string()),
+    }));
+    
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+// A tx counts as confirmed once every input it spent is gone from the UTXO
+// set. The inputs are only re-queried when the chain tip has moved. A failed
+// poll is logged and retried, so a flaky node reads as still pending rather
+// than as a failed transfer.
+async fn wait_for_tx(api_client: &ApiClient, tx_hash: Fr, spent_ids: &[Fr], timeout: Duration) -> bool {
+    let timeout = api_client.retry.bound(timeout);
+    let started = Instant::now();
+    let mut checked_tail = None;
+    loop {
+        match api_client.get_tail().await {
+            Ok(tail) if checked_tail.as_ref() != Some(&tail) => {
+                if api_client.inputs_spent(spent_ids).await {
+                    return true;
+                }
+                log::debug!("{} still pending at tip {}", tx_hash.to_hex(), tail);
+                checked_tail = Some(tail);
+            }
+            Ok(_) => {}
+            Err(err) => log::warn!("Polling for {} failed, will retry: {}", tx_hash.to_hex(), err),
+        }
+        if started.elapsed() >= timeout {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MergeInput {
+    Utxo(Fr),
+    // The merged output of an earlier step, by step index. Its id is only
+    // known once that step has confirmed.
+    Step(usize),
+}
+
+impl MergeInput {
+    fn label(self) -> String {
+        match self {
+            MergeInput::Utxo(id) => id.to_hex(),
+            MergeInput::Step(step) => format!("output of step {}", step + 1),
+        }
+    }
+}
+
+struct MergeStep {
+    inputs: Vec<(MergeInput, Amount)>,
+    merged: Amount,
+    change: Amount,
+}
+
+struct ConsolidationPlan {
+    steps: Vec<MergeStep>,
+    fee: Fr,
+    target: Option<Fr>,
+    total_fee: Amount,
+    initial_count: usize,
+    final_count: usize,
+    fee_limited: bool,
+}
+
+impl ConsolidationPlan {
+    // Everything is decided up front, so what is confirmed is exactly what
+    // gets submitted.
+    fn new(utxos: &[(Fr, Out)], fee: Fr, target: Option<Fr>) -> Result<Self> {
+        let mut pool: Vec<(MergeInput, Amount)> = utxos
+            .iter()
+            .filter(|(_, utxo)| !utxo.amount.is_zero())
+            .map(|(id, utxo)| (MergeInput::Utxo(*id), Amount(utxo.amount)))
+            .collect();
+        let initial_count = pool.len();
+        let (steps, fee_limited) = match target {
+            Some(target) => (target_steps(&mut pool, Amount(fee), Amount(target))?, false),
+            None => pairwise_steps(&mut pool, Amount(fee))?,
+        };
+        let total_fee = steps
+            .iter()
+            .try_fold(Amount::zero(), |total, _| total.checked_add(Amount(fee)))
+            .ok_or_else(|| anyhow!("total fee overflows the field"))?;
+        
+        Ok(Self { steps, fee, target, total_fee, initial_count, final_count: pool.len(), fee_limited })
+    }
+    
+    fn print(&self) {
+        say!("Consolidation plan:");
+        for (i, step) in self.steps.iter().enumerate() {
+            let inputs: Vec<String> = step
+                .inputs
+                .iter()
+                .map(|(input, amount)| format!("{} ({})", input.label(), format_amount(amount.0)))
+                .collect();
+            say!("  Step {}: merge {} -> {}", i + 1, inputs.join(" + "), format_amount(step.merged.0));
+        }
+        if self.fee_limited {
+            say!("  Stopping there: the two smallest UTXOs cannot cover the fee");
+        }
+        if let Some(target) = self.target {
+            if self.steps.is_empty() {
+                say!("  A coin of {} already exists", format_amount(target));
+            } else {
+                say!("  Result: one coin of {}", format_amount(target));
+            }
+        }
+        say!("  Fee: {} per merge, {} in total over {} merge(s)", format_amount(self.fee), format_amount(self.total_fee.0), self.steps.len());
+        say!("  UTXOs: {} -> {}", self.initial_count, self.final_count);
+    }
+    
+    fn to_json(&self) -> Value {
+        json!({
+            "steps": self.steps.iter().map(|step| json!({
+                "inputs": step.inputs.iter().map(|(input, amount)| json!({
+                    "input": input.label(),
+                    "amount": amount_json(amount.0),
+                })).collect::<Vec<_>>(),
+                "amount": amount_json(step.merged.0),
+                "change": amount_json(step.change.0),
+                "fee": amount_json(self.fee),
+            })).collect::<Vec<_>>(),
+            "target": self.target.map(amount_json),
+            "total_fee": amount_json(self.total_fee.0),
+            "initial_count": self.initial_count,
+            "final_count": self.final_count,
+        })
+    }
+}
+
+// Takes `inputs` out of the pool as one step, returning its outputs to it.
+fn push_step(pool: &mut Vec<(MergeInput, Amount)>, steps: &mut Vec<MergeStep>, inputs: Vec<(MergeInput, Amount)>, merged: Amount, change: Amount) {
+    pool.retain(|entry| !inputs.contains(entry));
+    for amount in [merged, change] {
+        if amount != Amount::zero() {
+            pool.push((MergeInput::Step(steps.len()), amount));
+        }
+    }
+    steps.push(MergeStep { inputs, merged, change });
+}
+
+// Merges the two smallest UTXOs until one is left or the smallest pair no
+// longer covers the fee; the flag reports the latter.
+fn pairwise_steps(pool: &mut Vec<(MergeInput, Amount)>, fee: Amount) -> Result<(Vec<MergeStep>, bool)> {
+    let mut steps = Vec::new();
+    while pool.len() >= 2 {
+        pool.sort_by_key(|(_, amount)| *amount);
+        let total = pool[0].1
+            .checked_add(pool[1].1)
+            .ok_or_else(|| anyhow!("input total overflows the field"))?;
+        let Some(merged) = total.checked_sub(fee) else {
+            return Ok((steps, true));
+        };
+        let inputs = pool[..2].to_vec();
+        push_step(pool, &mut steps, inputs, merged, Amount::zero());
+    }
+    Ok((steps, false))
+}
+
+// Builds one coin of exactly `target` plus change. The smallest UTXO that
+// can pay target and fee on its own is split; failing that, the largest
+// UTXOs are merged one by one until they can.
+fn target_steps(pool: &mut Vec<(MergeInput, Amount)>, fee: Amount, target: Amount) -> Result<Vec<MergeStep>> {
+    if target == Amount::zero() {
+        return Err(anyhow!("--target must be non-zero"));
+    }
+    let mut steps = Vec::new();
+    if pool.iter().any(|(_, amount)| *amount == target) {
+        return Ok(steps);
+    }
+    let needed = target.checked_add(fee).ok_or_else(|| anyhow!("target plus fee overflows the field"))?;
+    
+    pool.sort_by_key(|(_, amount)| std::cmp::Reverse(*amount));
+    if let Some(&single) = pool.iter().rev().find(|(_, amount)| *amount >= needed) {
+        let change = single.1.checked_sub(needed).unwrap_or(Amount::zero());
+        push_step(pool, &mut steps, vec![single], target, change);
+        return Ok(steps);
+    }
+    
+    let available = pool.iter().try_fold(Amount::zero(), |total, (_, amount)| total.checked_add(*amount));
+    let mut acc = *pool.first().ok_or_else(|| anyhow!("Account has no UTXOs to consolidate"))?;
+    for next in pool.clone().into_iter().skip(1) {
+        let total = acc.1
+            .checked_add(next.1)
+            .ok_or_else(|| anyhow!("input total overflows the field"))?;
+        if let Some(change) = total.checked_sub(needed) {
+            push_step(pool, &mut steps, vec![acc, next], target, change);
+            return Ok(steps);
+        }
+        let Some(merged) = total.checked_sub(fee) else { break };
+        push_step(pool, &mut steps, vec![acc, next], merged, Amount::zero());
+        acc = (MergeInput::Step(steps.len() - 1), merged);
+    }
+    Err(anyhow!(
+        "Target {} is not reachable: the UTXOs total {} and every merge costs a {} fee",
+        format_amount(target.0),
+        available.map_or_else(|| "more than the field".to_string(), |total| format_amount(total.0)),
+        format_amount(fee.0)
+    ))
+}
+
+// Finds a planned step's inputs in the current UTXO set. Anything that moved
+// since planning (spent elsewhere, a different amount, a merge output that
+// never showed up) stops the run rather than submitting an unplanned merge.
+fn resolve_merge_inputs(step: &MergeStep, utxos: &[(Fr, Out)], produced: &[Option<Fr>]) -> Result<Selection> {
+    let mut inputs = Vec::new();
+    for (input, amount) in &step.inputs {
+        let id = match input {
+            MergeInput::Utxo(id) => *id,
+            MergeInput::Step(k) => produced.get(*k).copied().flatten().ok_or_else(|| {
+                anyhow!("The {} never appeared among the account's UTXOs; rerun consolidate", input.label())
+            })?,
+        };
+        let utxo = utxos
+            .iter()
+            .find(|(utxo_id, _)| *utxo_id == id)
+            .ok_or_else(|| anyhow!("Planned input {} is no longer unspent; rerun consolidate", id.to_hex()))?;
+        if Amount(utxo.1.amount) != *amount {
+            return Err(anyhow!(
+                "Planned input {} now holds {} instead of {}; rerun consolidate",
+                id.to_hex(),
+                format_amount(utxo.1.amount),
+                format_amount(amount.0)
+            ));
+        }
+        inputs.push(utxo.clone());
+    }
+    Selection::from_inputs(inputs).ok_or_else(|| anyhow!("A merge step must have one or two inputs"))
+}
+
+// The id of the output a confirmed step created: the one UTXO that is new
+// since before the step and holds the merged amount.
+fn find_merge_output(before: &[(Fr, Out)], after: &[(Fr, Out)], merged: Amount) -> Option<Fr> {
+    after
+        .iter()
+        .find(|(id, utxo)| Amount(utxo.amount) == merged && !before.iter().any(|(old, _)| old == id))
+        .map(|(id, _)| *id)
+}
+
+// Zero-amount outputs (exact-spend change) are left out of every count, on
+// the dry-run path and after real merges alike.
+fn spendable_count(utxos: &[(Fr, Out)]) -> usize {
+    utxos.iter().filter(|(_, utxo)| !utxo.amount.is_zero()).count()
+}
+
+struct ConsolidateArgs<'a> {
+    account: &'a HexString,
+    fee: Fr,
+    target: Option<Fr>,
+    dry_run: bool,
+    yes: bool,
+    wait_timeout: Duration,
+}
+
+async fn run_consolidate(api_client: &ApiClient, cli: &Cli, args: ConsolidateArgs<'_>, secret: &Secret) -> Result<()> {
+    let ConsolidateArgs { account, fee, target, dry_run, yes, wait_timeout } = args;
+    if !dry_run {
+        require_yes_for_json(yes)?;
+    }
+    let owner = parse_address(account)?;
+    let own_address = decode_prover_address(&generate_address(secret)?)?;
+    // Checked before planning, so even a dry run can't plan with the wrong
+    // secret.
+    if own_address != owner {
+        return Err(anyhow!("This secret belongs to {}, but --account is {}", own_address.to_hex(), account));
+    }
+    
+    let mut utxos = fetch_owned_utxos(api_client, account, owner, UtxoDetail::Full).await?;
+    let plan = ConsolidationPlan::new(&utxos, fee, target)?;
+    log::info!("Consolidating {} UTXOs of {}", plan.initial_count, account);
+    plan.print();
+    let mut report = plan.to_json();
+    report["dry_run"] = json!(dry_run);
+    
+    if dry_run || plan.steps.is_empty() {
+        if dry_run {
+            say!("\nDry run: nothing submitted");
+        }
+        output::emit(&report);
+        return Ok(());
+    }
+    if !yes {
+        confirm("submit")?;
+    }
+    
+    let mut produced = Vec::new();
+    for (i, step) in plan.steps.iter().enumerate() {
+        let selection = resolve_merge_inputs(step, &utxos, &produced)?;
+        let tx = construct_transfer_tx(&selection, own_address, step.merged.0, fee, own_address)?;
+        if Amount(tx.oy.amount) != step.change {
+            return Err(anyhow!("Merge {} would leave change {} instead of the planned {}", i + 1, format_amount(tx.oy.amount), format_amount(step.change.0)));
+        }
+        
+        let wp_tx_bytes = prove_tx(&tx, Authority::Secret(secret), own_address, false, true, cli.vk_cache.as_deref())?;
+        let tx_hash = tx.hash();
+        api_client
+            .submit_idempotent(&hex::encode(&wp_tx_bytes), &tx_hash.to_hex(), &[tx.ix, tx.iy])
+            .await
+            .map_err(|err| anyhow!("Failed to submit merge {}: {}", i + 1, err))?;
+        log::info!("Submitted {}, waiting for confirmation...", format_hash(tx_hash, cli.hash_format));
+        if !wait_for_tx(api_client, tx_hash, &[tx.ix, tx.iy], wait_timeout).await {
+            return Err(anyhow!(
+                "Merge {} ({}) is still pending after {}s; rerun consolidate once it confirms",
+                i + 1,
+                tx_hash.to_hex(),
+                wait_timeout.as_secs()
+            ));
+        }
+        say!("Merge {} confirmed: {}", i + 1, format_hash(tx_hash, cli.hash_format));
+        report["steps"][i]["tx_hash"] = json!(format_hash(tx_hash, cli.hash_format));
+        
+        let refreshed = fetch_owned_utxos(api_client, account, owner, UtxoDetail::Full).await?;
+        produced.push(find_merge_output(&utxos, &refreshed, step.merged));
+        utxos = refreshed;
+    }
+    
+    let final_count = spendable_count(&utxos);
+    if final_count != plan.final_count {
+        log::warn!("Planned {} UTXOs after consolidating but the account now has {}", plan.final_count, final_count);
+    }
+    say!("\nMerged {} time(s), total fee {}", plan.steps.len(), format_amount(plan.total_fee.0));
+    say!("UTXOs: {} -> {}", plan.initial_count, final_count);
+    report["final_count"] = json!(final_count);
+    output::emit(&report);
+    Ok(())
+}
+
+async fn build_unsigned(api_client: &ApiClient, from: &HexString, to: &HexString, amount: Fr, fee: Fr) -> Result<UnsignedTx> {
+    let from_fr = parse_address(from)?;
+    reject_zero("from", from_fr)?;
+    let to_fr = parse_address(to)?;
+    
+    let utxos = fetch_owned_utxos(api_client, from, from_fr, UtxoDetail::Full).await?;
+    let Some(selection) = select_utxos(utxos.clone(), amount, fee, CoinSelection::FirstFit) else {
+        return Err(explain_unselectable(&utxos, amount, fee));
+    };
+    let inputs = selection
+        .inputs()
+        .iter()
+        .map(|(id, utxo)| UnsignedInput { id: id.to_hex(), amount: utxo.amount.to_hex() })
+        .collect();
+    // The change owner is only known once signing derives it, so a zero
+    // placeholder is used here just to compute the change amount.
+    let tx = construct_transfer_tx(&selection, to_fr, amount, fee, Fr::from(0u32))?;
+    
+    Ok(UnsignedTx {
+        version: artifact::ARTIFACT_VERSION,
+        from: from_fr.to_hex(),
+        inputs,
+        to: to_fr.to_hex(),
+        amount: amount.to_hex(),
+        fee: fee.to_hex(),
+        change: tx.oy.amount.to_hex(),
+    })
+}
+
+// The file comes from the online machine, so everything it asks to be
+// signed is shown and confirmed before proving.
+fn sign_unsigned(unsigned: &UnsignedTx, secret: &Secret, vk_cache: Option<&Path>, yes: bool) -> Result<(Tx, Vec<u8>)> {
+    require_yes_for_json(yes)?;
+    let mut inputs = Vec::new();
+    for input in &unsigned.inputs {
+        inputs.push((artifact::fr("input id", &input.id)?, artifact::fr("input amount", &input.amount)?));
+    }
+    if inputs.is_empty() || inputs.len() > 2 {
+        return Err(anyhow!("Unsigned transaction must have one or two inputs, got {}", inputs.len()));
+    }
+    let from = artifact::fr("from", &unsigned.from)?;
+    let to = artifact::fr("to", &unsigned.to)?;
+    let amount = artifact::fr("amount", &unsigned.amount)?;
+    let fee = artifact::fr("fee", &unsigned.fee)?;
+    let change = artifact::fr("change", &unsigned.change)?;
+    
+    let change_to = decode_prover_address(&generate_address(secret)?)?;
+    if change_to != from {
+        return Err(anyhow!(
+            "This secret belongs to {}, but the unsigned transaction spends from {}",
+            change_to.to_hex(),
+            unsigned.from
+        ));
+    }
+    let iy = inputs.get(1).map_or(Fr::from(0u32), |(id, _)| *id);
+    let tx = construct_tx(inputs[0].0, iy, (to, amount), (change_to, change));
+    check_value_conserved(&inputs, &tx, fee)?;
+    
+    if !yes {
+        say!("About to sign:");
+        say!("  From: {}", from.to_hex());
+        say!("  To: {}", to.to_hex());
+        say!("  Amount: {}", format_amount(amount));
+        say!("  Fee: {}", format_amount(fee));
+        say!("  Change: {}", format_amount(change));
+        for (id, amount) in &inputs {
+            say!("  Input {}: {}", id.to_hex(), format_amount(*amount));
+        }
+        confirm("sign")?;
+    }
+    
+    // There is no node to simulate against offline, so always verify locally.
+    let wp_tx_bytes = prove_tx(&tx, Authority::Secret(secret), change_to, true, true, vk_cache)?;
+    Ok((tx, wp_tx_bytes))
+}
+
+struct SendMaxArgs<'a> {
+    from: &'a HexString,
+    to: &'a HexString,
+    fee: Fr,
+    dry_run: bool,
+    yes: bool,
+}
+
+// Sweeps the two largest UTXOs (all a Tx can spend) to `to`. The change
+// output still exists, since a Tx always has two, but carries zero.
+async fn run_send_max(api_client: &ApiClient, cli: &Cli, args: SendMaxArgs<'_>, secret: &Secret) -> Result<()> {
+    if !args.dry_run {
+        require_yes_for_json(args.yes)?;
+    }
+    let from_fr = parse_address(args.from)?;
+    reject_zero("from", from_fr)?;
+    let to_fr = parse_address(args.to)?;
+    let change_to = decode_prover_address(&generate_address(secret)?)?;
+    if to_fr == change_to || to_fr == from_fr {
+        return Err(anyhow!("--to {} is this wallet's own address; sweeping to yourself only burns the fee", args.to));
+    }
+    
+    let utxos = fetch_owned_utxos(api_client, args.from, from_fr, UtxoDetail::Full).await?;
+    let (selection, sendable) = max_sendable(&utxos, args.fee)
+        .ok_or_else(|| anyhow!("Nothing to send: the UTXOs of {} do not cover the fee", args.from))?;
+    if sendable == Amount::zero() {
+        return Err(anyhow!("Nothing to send: the UTXOs of {} only cover the fee", args.from));
+    }
+    let spent: Vec<(Fr, Fr)> = selection.inputs().iter().map(|(id, utxo)| (*id, utxo.amount)).collect();
+    
+    let tx = construct_transfer_tx(&selection, to_fr, sendable.0, args.fee, change_to)?;
+    check_value_conserved(&spent, &tx, args.fee)?;
+    let tx_hash = tx.hash();
+    
+    say!("Sweep plan:");
+    for (id, amount) in &spent {
+        say!("  Input {}: {}", id.to_hex(), format_amount(*amount));
+    }
+    say!("  Pay {} to {}", format_amount(tx.ox.amount), to_fr.to_hex());
+    say!("  Fee: {}", format_amount(args.fee));
+    say!("  Transaction hash: {}", format_hash(tx_hash, cli.hash_format));
+    if utxos.iter().filter(|(_, utxo)| !utxo.amount.is_zero()).count() > spent.len() {
+        log::warn!("{} has more UTXOs than one tx can spend; run send-max again or consolidate first", args.from);
+    }
+    
+    let wp_tx_bytes = prove_tx(&tx, Authority::Secret(secret), change_to, false, true, cli.vk_cache.as_deref())?;
+    let mut report = json!({
+        "tx_hash": format_hash(tx_hash, cli.hash_format),
+        "amount": amount_json(sendable.0),
+        "fee": amount_json(args.fee),
+        "inputs": spent.iter().map(|(id, _)| id.to_hex()).collect::<Vec<_>>(),
+    });
+    if args.dry_run {
+        say!("Dry run: not submitting");
+        report["status"] = json!("dry_run");
+        report["wp_tx"] = json!(hex::encode(&wp_tx_bytes));
+        output::emit(&report);
+        return Ok(());
+    }
+    
+    if !args.yes {
+        confirm("submit")?;
+    }
+    let submission = api_client
+        .submit_idempotent(&hex::encode(&wp_tx_bytes), &tx_hash.to_hex(), &[tx.ix, tx.iy])
+        .await
+        .map_err(|err| anyhow!("Failed to submit transaction: {}", err))?;
+    if submission == Submission::AlreadyKnown {
+        say!("Transaction was already submitted by an earlier attempt");
+    }
+    say!("Transaction hash: {}", format_hash(tx_hash, cli.hash_format));
+    report["status"] = json!(submission.status());
+    output::emit(&report);
+    Ok(())
+}
+
+fn report_simulation(verdict: Option<String>) -> Value {
+    match &verdict {
+        None => say!("Simulation: transaction would be accepted"),
+        Some(reason) => say!("Simulation: transaction would be rejected: {}", reason),
+    }
+    json!({
+        "status": if verdict.is_none() { "accepted" } else { "rejected" },
+        "reason": verdict,
+    })
+}
+
+fn format_hash(hash: Fr, format: HashFormat) -> String {
+    match format {
+        HashFormat::Hex => hash.to_hex(),
+        HashFormat::Prefixed => format!("0x{}", hash.to_hex()),
+        HashFormat::Base58 => bs58::encode(hash.enc().collect::<Vec<u8>>()).into_string(),
+    }
+}
+
+// A Wp (the form submit and the archives carry) is tried first. A bare Tx
+// is only accepted if it re-encodes to exactly the input, so a Wp prefix
+// can never be misread as some other transaction.
+fn decode_tx(tx_hex: &str) -> Result<Tx> {
+    let bytes = hex::decode(tx_hex.trim_start_matches("0x"))?;
+    let mut iter = bytes.iter().copied();
+    if let Ok(Wp { val, .. }) = Wp::<Tx>::dec(&mut iter) {
+        if iter.next().is_none() {
+            return Ok(val);
+        }
+    }
+    
+    match Tx::dec(&mut bytes.iter().copied()) {
+        Ok(tx) if tx.clone().enc().eq(bytes.iter().copied()) => Ok(tx),
+        _ => Err(anyhow!("Input is neither an encoded Wp nor an encoded Tx")),
+    }
+}
+
+fn fr_to_biguint(fr: Fr) -> BigUint {
+    BigUint::from_bytes_be(&fr.enc().collect::<Vec<u8>>())
+}
+
+// No real supply comes near 2^128; values above it are almost always a
+// wrapped subtraction (negative change) rather than a genuine amount.
+const MAX_PLAUSIBLE_AMOUNT_BITS: u64 = 128;
+
+fn is_implausible_amount(amount: Fr) -> bool {
+    fr_to_biguint(amount).bits() > MAX_PLAUSIBLE_AMOUNT_BITS
+}
+
+// Amounts are whole base units; the field tells JSON consumers so rather
+// than leaving them to guess.
+const AMOUNT_DECIMALS: u32 = 0;
+
+fn amount_json(amount: Fr) -> Value {
+    json!({
+        "hex": amount.to_hex(),
+        "decimal": fr_to_biguint(amount).to_string(),
+        "decimals": AMOUNT_DECIMALS,
+    })
+}
+
+struct UtxoSummary {
+    total: Amount,
+    largest: Option<Amount>,
+    smallest: Option<Amount>,
+}
+
+fn summarize_utxos(utxos: &[Out]) -> Result<UtxoSummary> {
+    let total = utxos
+        .iter()
+        .try_fold(Amount::zero(), |total, utxo| total.checked_add(Amount(utxo.amount)))
+        .ok_or_else(|| anyhow!("UTXO total overflows the field"))?;
+    Ok(UtxoSummary {
+        total,
+        largest: utxos.iter().map(|utxo| Amount(utxo.amount)).max(),
+        smallest: utxos.iter().map(|utxo| Amount(utxo.amount)).min(),
+    })
+}
+
+// The node's balance, checked against the sum of the account's UTXOs; a
+// disagreement is only warned about, and `source` picks which one is used.
+async fn cross_checked_balance(api_client: &ApiClient, account: &HexString, source: BalanceSource) -> Result<Fr> {
+    let node_balance = api_client
+        .get_balance(&account.to_string())
+        .await
+        .map_err(|err| anyhow!("Failed to get balance: {}", err))
+        .and_then(|balance_hex| decode_balance(&balance_hex))?;
+    
+    let utxo_balance = match fetch_all_utxos(api_client, account).await {
+        Ok(utxos) => Some(utxos.iter().fold(Fr::from(0u32), |total, utxo| total + utxo.amount)),
+        Err(err) => {
+            log::warn!("Could not sum UTXOs to cross-check the balance: {}", err);
+            None
+        }
+    };
+    
+    if let Some(utxo_balance) = utxo_balance {
+        if utxo_balance != node_balance {
+            log::warn!(
+                "Node balance {} disagrees with summed UTXOs {}",
+                node_balance.to_hex(),
+                utxo_balance.to_hex()
+            );
+        }
+    }
+    
+    Ok(match source {
+        BalanceSource::Node => node_balance,
+        BalanceSource::Utxos => utxo_balance.ok_or_else(|| anyhow!("Failed to sum UTXOs for account {}", account))?,
+    })
+}
+
+fn balance_report(balance: Fr) -> Value {
+    json!({ "balance": amount_json(balance) })
+}
+
+fn format_amount(amount: Fr) -> String {
+    let text = format!("amount={} (0x{})", fr_to_biguint(amount), amount.to_hex());
+    if is_implausible_amount(amount) {
+        format!("{} [likely underflow/invalid]", text)
+    } else {
+        text
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct OutFormat {
+    owner: bool,
+    data: bool,
+}
+
+fn format_out(out: &Out, opts: OutFormat) -> String {
+    let mut text = format_amount(out.amount);
+    if opts.owner {
+        text.push_str(&format!(", owner={}", out.owner.to_hex()));
+    }
+    if opts.data {
+        let data: Vec<String> = out.data.iter().map(|d| d.to_hex()).collect();
+        text.push_str(&format!(", data=[{}]", data.join(", ")));
+    }
+    text
+}
+
+fn read_archive(path: &Path) -> Result<Value> {
+    let raw = fs::read(path)?;
+    let contents = if raw.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = Vec::new();
+        GzDecoder::new(raw.as_slice()).read_to_end(&mut decoded)?;
+        decoded
+    } else {
+        raw
+    };
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+fn out_to_json(out: &Out) -> Value {
+    json!({
+        "amount": amount_json(out.amount),
+        "likely_underflow": is_implausible_amount(out.amount),
+        "owner": out.owner.to_hex(),
+        "data": out.data.iter().map(|d| d.to_hex()).collect::<Vec<_>>(),
+    })
+}
+
+fn tx_summary(tx: &Tx) -> Value {
+    json!({
+        "ix": tx.ix.to_hex(),
+        "iy": tx.iy.to_hex(),
+        "ox": out_to_json(&tx.ox),
+        "oy": out_to_json(&tx.oy),
+    })
+}
+
+fn archive_submission(dir: &Path, tx: &Tx, wp_tx_bytes: &[u8], gzip: bool) -> Result<PathBuf> {
+    let tx_hash = tx.hash().to_hex();
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let record = json!({
+        "tx_hash": tx_hash,
+        "timestamp": timestamp,
+        "wire_version": codec::WIRE_VERSION,
+        "wp_tx": hex::encode(wp_tx_bytes),
+        "tx": tx_summary(tx),
+    });
+    
+    let mut contents = serde_json::to_vec_pretty(&record)?;
+    let file_name = if gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&contents)?;
+        contents = encoder.finish()?;
+        format!("{}.json.gz", tx_hash)
+    } else {
+        format!("{}.json", tx_hash)
+    };
+    
+    fs::create_dir_all(dir)?;
+    let path = dir.join(&file_name);
+    let tmp_path = dir.join(format!(".{}.tmp", file_name));
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, &path)?;
+    
+    Ok(path)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    output::set_json(cli.json);
+    
+    let result = match config::load(cli.config.as_deref()) {
+        Ok(config) => match cli.apply_config(&config, &matches) {
+            Ok(()) => run(&cli).await,
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err),
+    };
+    passphrase::forget();
+    match result {
+        Err(err) if output::json() => {
+            if !output::emitted() {
+                println!("{}", json!({ "error": format!("{:#}", err) }));
+            }
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
+
+fn payment_request(account: &HexString, amount: Option<&HexString>, memo: Option<&String>) -> Result<(PaymentRequest, String)> {
+    let request = PaymentRequest {
+        account: parse_address(account)?,
+        amount: amount.cloned().map(HexConverter::from_hex).transpose()?,
+        memo: memo.cloned(),
+    };
+    let uri = request.to_uri();
+    
+    let parsed = PaymentRequest::parse(&uri)?;
+    if parsed.account != request.account || parsed.amount != request.amount || parsed.memo != request.memo {
+        return Err(anyhow!("Payment URI does not round-trip: {}", uri));
+    }
+    Ok((request, uri))
+}
+
+async fn run(cli: &Cli) -> Result<()> {
+    let offline = cli.no_network || cli.command.is_offline();
+    let api_client = ApiClient::new(
+        cli.api_url.clone(),
+        Duration::from_secs(cli.timeout_secs),
+        RetryPolicy {
+            retries: cli.retries,
+            max_backoff: Duration::from_millis(cli.max_backoff_ms),
+            deadline: cli.deadline_secs.map(|secs| Instant::now() + Duration::from_secs(secs)),
+        },
+        Duration::from_secs(cli.submit_timeout),
+        offline,
+        &cli.tls,
+        Duration::from_millis(cli.tail_cache_ms),
+    )?;
+
+    match &cli.command {
+        Commands::Create { confirm_address_derivation, seed, no_keystore, passphrase } => {
+            say!("Creating new wallet account...");
+            
+            let secret = match seed {
+                Some(seed) => {
+                    log::warn!("--seed makes the secret reproducible by anyone who knows the seed; never use it for real funds");
+                    seeded_secret(seed)?
+                }
+                None => create_secret(&mut OsRng),
+            };
+            let vk_hex = generate_address(&secret).map_err(|err| anyhow!("Failed to generate VK: {}", err))?;
+            if *confirm_address_derivation {
+                let rederived = generate_address(&secret)?;
+                if rederived != vk_hex {
+                    return Err(anyhow!(
+                        "Address derivation is not deterministic: got {} then {}; do not use this account",
+                        vk_hex,
+                        rederived
+                    ));
+                }
+                say!("Address derivation confirmed");
+            }
+            
+            if *no_keystore {
+                say!("Secret: {}", *secret.to_hex());
+                say!("Account (VK): {}", vk_hex);
+                output::emit(&json!({ "secret": *secret.to_hex(), "account": vk_hex }));
+            } else {
+                let passphrase = passphrase.read(true)?;
+                let path = keystore::store(&vk_hex, &secret, &passphrase)?;
+                verify_stored_account(&path, &passphrase, &vk_hex)?;
+                say!("Account (VK): {}", vk_hex);
+                say!("Secret encrypted to {}", path.display());
+                output::emit(&json!({ "account": vk_hex, "keystore": path.display().to_string() }));
+            }
+        }
+        
+        Commands::DeriveAddress { secret } => {
+            let secret = Secret::from_hex(secret)?;
+            reject_zero_secret(&secret)?;
+            let account = generate_address(&secret).map_err(|err| anyhow!("Failed to derive address: {}", err))?;
+            say!("Account (VK): {}", account);
+            output::emit(&json!({ "account": account }));
+        }
+        
+        Commands::GetBalance { account, balance_source } => {
+            parse_address(account)?;
+            say!("Getting balance for account: {}", account);
+            
+            let balance = cross_checked_balance(&api_client, account, *balance_source).await?;
+            say!("Balance: {}", fr_to_biguint(balance));
+            output::emit(&balance_report(balance));
+        }
+        
+        Commands::ListUtxos { account, show_data } => {
+            parse_address(account)?;
+            say!("Listing UTXOs for account: {}", account);
+            
+            let utxos = fetch_all_utxos(&api_client, account)
+                .await
+                .map_err(|err| anyhow!("Failed to get UTXOs: {}", err))?;
+            for (i, utxo) in utxos.iter().enumerate() {
+                say!("UTXO #{}: {}",
+                    i + 1,
+                    format_out(utxo, OutFormat { owner: true, data: *show_data })
+                );
+            }
+            
+            say!("\nTotal UTXOs found: {}", utxos.len());
+            let UtxoSummary { total, largest, smallest } = summarize_utxos(&utxos)?;
+            say!("Total spendable: {}", total.value());
+            if let (Some(largest), Some(smallest)) = (largest, smallest) {
+                say!("Largest UTXO: {}", largest.value());
+                say!("Smallest UTXO: {}", smallest.value());
+            }
+            
+            match api_client.get_balance(&account.to_string()).await.and_then(|hex| decode_balance(&hex)) {
+                Ok(balance) if Amount(balance) != total => log::warn!(
+                    "Summed UTXOs {} disagree with the node balance {}",
+                    total.value(),
+                    fr_to_biguint(balance)
+                ),
+                Ok(_) => {}
+                Err(err) => log::warn!("Could not fetch the node balance to cross-check the UTXO total: {}", err),
+            }
+            
+            output::emit(&json!({
+                "utxos": utxos.iter().map(out_to_json).collect::<Vec<_>>(),
+                "total": utxos.len(),
+                "total_amount": amount_json(total.0),
+                "largest": largest.map(|amount| amount_json(amount.0)),
+                "smallest": smallest.map(|amount| amount_json(amount.0)),
+            }));
+        }
+        
+        Commands::SendMax { from, to, secret, fee, dry_run, yes } => {
+            let secret = Secret::from_hex(secret)?;
+            reject_zero_secret(&secret)?;
+            let fee = parse_fee(fee.as_ref())?;
+            let args = SendMaxArgs { from, to, fee, dry_run: *dry_run, yes: *yes };
+            run_send_max(&api_client, cli, args, &secret).await?;
+        }
+        
+        Commands::EstimateFee { from, amount, fee } => {
+            let from_fr = parse_address(from)?;
+            let amount: Fr = HexConverter::from_hex(amount.clone())?;
+            let fee = parse_fee(fee.as_ref())?;
+            let required = Amount(amount)
+                .checked_add(Amount(fee))
+                .ok_or_else(|| anyhow!("amount plus fee overflows the field"))?;
+            
+            let utxos = fetch_owned_utxos(&api_client, from, from_fr, UtxoDetail::Full).await?;
+            let coverable = select_utxos(utxos.clone(), amount, fee, CoinSelection::FirstFit).is_some();
+            let max = max_sendable(&utxos, fee).map(|(_, sendable)| sendable);
+            
+            say!("Fee: {}", format_amount(fee));
+            say!("Required total: {}", required.value());
+            if coverable {
+                say!("Current UTXOs can cover this transfer");
+            } else {
+                say!("Current UTXOs cannot cover this transfer: {}", explain_unselectable(&utxos, amount, fee));
+            }
+            match max {
+                Some(max) => say!("Maximum sendable after fees: {}", max.value()),
+                None => say!("Maximum sendable after fees: 0 (UTXOs do not cover the fee)"),
+            }
+            output::emit(&json!({
+                "fee": amount_json(fee),
+                "required": amount_json(required.0),
+                "coverable": coverable,
+                "max_sendable": amount_json(max.unwrap_or(Amount::zero()).0),
+            }));
+        }
+        
+        Commands::GetUtxo { id } => {
+            let id: Fr = HexConverter::from_hex(id.clone())?;
+            let utxo_hex = api_client.get_utxo(&id.to_hex()).await?;
+            if utxo_hex.is_empty() {
+                return Err(anyhow!("UTXO {} not found", id.to_hex()));
+            }
+            let utxo = decode_utxo(&utxo_hex)?;
+            
+            say!("UTXO: {}", id.to_hex());
+            say!("  Amount: {}", format_amount(utxo.amount));
+            say!("  Owner: {}", utxo.owner.to_hex());
+            if utxo.data.is_empty() {
+                say!("  Data: none");
+            }
+            for (i, entry) in utxo.data.iter().enumerate() {
+                say!("  Data[{}]: {}", i, entry.to_hex());
+            }
+            let mut report = out_to_json(&utxo);
+            report["id"] = json!(id.to_hex());
+            output::emit(&report);
+        }
+        
+        Commands::Transfer { args, secret, account, passphrase } => {
+            let secret = match (secret, account) {
+                (Some(secret), _) => Secret::from_hex(secret)?,
+                (None, Some(account)) => {
+                    parse_address(account)?;
+                    passphrase.unlock(|passphrase| keystore::load(&account.to_string(), passphrase))?
+                }
+                (None, None) => return Err(anyhow!("Either --secret or --account is required")),
+            };
+            reject_zero_secret(&secret)?;
+            run_transfers(&api_client, cli, args, Authority::Secret(&secret)).await?;
+        }
+        
+        Commands::TransferPermissionless { args } => {
+            run_transfers(&api_client, cli, args, Authority::Permissionless).await?;
+        }
+        
+        Commands::TxHash { tx_hex } => {
+            let tx = decode_tx(tx_hex)?;
+            say!("Transaction hash: {}", format_hash(tx.hash(), cli.hash_format));
+            output::emit(&json!({ "tx_hash": format_hash(tx.hash(), cli.hash_format) }));
+        }
+        
+        Commands::SimulateTx { tx_hex } => {
+            let tx_hex = tx_hex.trim_start_matches("0x");
+            output::emit(&report_simulation(api_client.validate_transaction(tx_hex).await?));
+        }
+        
+        Commands::ShowArchive { file } => {
+            let record = read_archive(file)?;
+            codec::check_version(&record)?;
+            let wp_tx_hex = record["wp_tx"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Archive {} has no wp_tx field", file.display()))?;
+            let bytes = hex::decode(wp_tx_hex)?;
+            let mut iter = bytes.iter().copied();
+            let Wp { val: tx, .. } = Wp::<Tx>::dec(&mut iter)?;
+            if iter.next().is_some() {
+                return Err(anyhow!("Archive {} has trailing bytes after its wp_tx", file.display()));
+            }
+            
+            say!("Archived at: {}", record["timestamp"]);
+            say!("Transaction hash: {}", format_hash(tx.hash(), cli.hash_format));
+            say!("{}", serde_json::to_string_pretty(&tx_summary(&tx))?);
+            output::emit(&json!({
+                "timestamp": record["timestamp"],
+                "tx_hash": format_hash(tx.hash(), cli.hash_format),
+                "tx": tx_summary(&tx),
+            }));
+        }
+        
+        Commands::Coins { account, select, stage_file } => {
+            let account_fr = parse_address(account)?;
+            let mut selected_ids = Vec::new();
+            for id in select {
+                selected_ids.push(HexConverter::from_hex(id.clone())?);
+            }
+            
+            let coins = fetch_owned_utxos(&api_client, account, account_fr, UtxoDetail::Full).await?;
+            
+            for (id, utxo) in &coins {
+                let mark = if selected_ids.contains(id) { "x" } else { " " };
+                say!("[{}] {} {}", mark, id.to_hex(), format_out(utxo, OutFormat::default()));
+            }
+            say!("\nTotal coins: {}", coins.len());
+            let mut report = json!({
+                "coins": coins.iter().map(|(id, utxo)| {
+                    let mut coin = out_to_json(utxo);
+                    coin["id"] = json!(id.to_hex());
+                    coin["selected"] = json!(selected_ids.contains(id));
+                    coin
+                }).collect::<Vec<_>>(),
+            });
+            
+            if !selected_ids.is_empty() {
+                let staged = stage_record(account, &coins, &selected_ids)?;
+                fs::write(stage_file, serde_json::to_vec_pretty(&staged)?)?;
+                say!("Staged {} coin(s) in {}; pass --inputs-file {} to a transfer", selected_ids.len(), stage_file.display(), stage_file.display());
+                report["stage_file"] = json!(stage_file.display().to_string());
+            }
+            output::emit(&report);
+        }
+        
+        Commands::Consolidate { account, secret, fee, dry_run, wait_timeout, yes, target } => {
+            let secret = Secret::from_hex(secret)?;
+            reject_zero_secret(&secret)?;
+            let fee = parse_fee(fee.as_ref())?;
+            let target = target.as_ref().map(|target| HexConverter::from_hex(target.clone())).transpose()?;
+            let args = ConsolidateArgs {
+                account,
+                fee,
+                target,
+                dry_run: *dry_run,
+                yes: *yes,
+                wait_timeout: Duration::from_secs(*wait_timeout),
+            };
+            run_consolidate(&api_client, cli, args, &secret).await?;
+        }
+        
+        Commands::VerifyProof { tx_hex } => {
+            let bytes = hex::decode(tx_hex.trim_start_matches("0x"))?;
+            let Wp { vk, proof, val } = Wp::dec(&mut bytes.into_iter())?;
+            let tx: Tx = val;
+            
+            let valid = verify_proof(&vk, &proof, &tx);
+            say!("Transaction hash: {}", format_hash(tx.hash(), cli.hash_format));
+            if valid {
+                say!("Proof verification passed");
+            } else {
+                say!("Proof verification FAILED");
+            }
+            output::emit(&json!({
+                "tx_hash": format_hash(tx.hash(), cli.hash_format),
+                "valid": valid,
+            }));
+            if !valid {
+                return Err(anyhow!("Proof does not verify against the transaction"));
+            }
+        }
+        
+        Commands::Receive { account, amount, memo } => {
+            let (request, uri) = payment_request(account, amount.as_ref(), memo.as_ref())?;
+            
+            say!("Account: {}", request.account.to_hex());
+            if let Some(amount) = request.amount {
+                say!("Requested amount: {}", format_amount(amount));
+            }
+            if let Some(memo) = &request.memo {
+                say!("Memo: {}", memo);
+            }
+            say!("URI: {}", uri);
+            output::emit(&json!({
+                "account": request.account.to_hex(),
+                "amount": request.amount.map(amount_json),
+                "memo": request.memo,
+                "uri": uri,
+            }));
+        }
+        
+        Commands::BuildUnsigned { from, to, amount, fee, out } => {
+            let amount = HexConverter::from_hex(amount.clone())?;
+            let fee = parse_fee(fee.as_ref())?;
+            let unsigned = build_unsigned(&api_client, from, to, amount, fee).await?;
+            artifact::write(out, &unsigned)?;
+            
+            for input in &unsigned.inputs {
+                say!("Input {}: {}", input.id, input.amount);
+            }
+            say!("Change: {}", unsigned.change);
+            say!("Unsigned transaction written to {}; sign it offline with sign-tx", out.display());
+            output::emit(&json!({ "file": out.display().to_string(), "unsigned": unsigned }));
+        }
+        
+        Commands::SignTx { file, secret, out, yes } => {
+            let secret = Secret::from_hex(secret)?;
+            reject_zero_secret(&secret)?;
+            let unsigned: UnsignedTx = artifact::read(file)?;
+            let (tx, wp_tx_bytes) = sign_unsigned(&unsigned, &secret, cli.vk_cache.as_deref(), *yes)?;
+            
+            let signed = SignedTx {
+                version: artifact::ARTIFACT_VERSION,
+                tx_hash: tx.hash().to_hex(),
+                wp_tx: hex::encode(&wp_tx_bytes),
+            };
+            artifact::write(out, &signed)?;
+            say!("Transaction hash: {}", format_hash(tx.hash(), cli.hash_format));
+            say!("Signed transaction written to {}; broadcast it with submit-tx", out.display());
+            output::emit(&json!({
+                "file": out.display().to_string(),
+                "tx_hash": format_hash(tx.hash(), cli.hash_format),
+            }));
+        }
+        
+        Commands::SubmitTx { file } => {
+            let signed: SignedTx = artifact::read(file)?;
+            // wp_tx is the Wp encoding exactly as it will be submitted, so it has
+            // to decode with nothing left over.
+            let mut bytes = hex::decode(&signed.wp_tx)?.into_iter();
+            let Wp { val: tx, .. } = Wp::<Tx>::dec(&mut bytes)?;
+            if bytes.next().is_some() {
+                return Err(anyhow!("{} has trailing bytes after the signed transaction", file.display()));
+            }
+            let claimed: Fr = HexConverter::from_hex(
+                signed.tx_hash.parse().map_err(|_| anyhow!("{} has an invalid tx_hash", file.display()))?,
+            )?;
+            if tx.hash() != claimed {
+                return Err(anyhow!(
+                    "{} is inconsistent: tx_hash is {} but the transaction hashes to {}",
+                    file.display(), signed.tx_hash, tx.hash().to_hex()
+                ));
+            }
+            let submission = api_client
+                .submit_idempotent(&signed.wp_tx, &signed.tx_hash, &[tx.ix, tx.iy])
+                .await
+                .map_err(|err| anyhow!("Failed to submit transaction: {}", err))?;
+            if submission == Submission::AlreadyKnown {
+                say!("Transaction was already submitted by an earlier attempt");
+            }
+            say!("Transaction hash: {}", signed.tx_hash);
+            output::emit(&json!({ "tx_hash": signed.tx_hash, "status": submission.status() }));
+        }
+        
+        Commands::ExportAccount { account, out, passphrase } => {
+            parse_address(account)?;
+            passphrase.unlock(|passphrase| keystore::export(&account.to_string(), out, passphrase))?;
+            say!("Encrypted keystore for {} exported to {}", account, out.display());
+            output::emit(&json!({ "account": account.to_string(), "file": out.display().to_string() }));
+        }
+        
+        Commands::ImportAccount { file, force, passphrase } => {
+            let record = keystore::read_record(file)?;
+            let account: HexString = record["account"]
+                .as_str()
+                .and_then(|account| account.parse().ok())
+                .ok_or_else(|| anyhow!("{} has no valid account field", file.display()))?;
+            let account_fr = parse_address(&account)?;
+            
+            let secret = passphrase.unlock(|passphrase| keystore::decrypt(&record, file, passphrase))?;
+            let derived = generate_address(&secret)?;
+            if decode_prover_address(&derived)? != account_fr {
+                return Err(anyhow!("{} decrypts to the secret of {}, not {}", file.display(), derived, account));
+            }
+            
+            let path = keystore::import(&account.to_string(), &record, *force)?;
+            say!("Imported {} into {}", account, path.display());
+            output::emit(&json!({ "account": account.to_string(), "keystore": path.display().to_string() }));
+        }
+        
+        Commands::NodeInfo => {
+            let started = Instant::now();
+            let tip = api_client
+                .refresh_tail()
+                .await
+                .map_err(|err| anyhow!("Node at {} is unreachable: {}", cli.api_url, err))?;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            let version = api_client.get_version().await;
+            
+            say!("Node: {}", cli.api_url);
+            say!("Chain tip: {}", tip);
+            say!("Latency: {} ms", latency_ms);
+            if let Some(version) = &version {
+                say!("Version: {}", version);
+            }
+            output::emit(&json!({ "tip": tip, "latency_ms": latency_ms, "version": version }));
+        }
+        
+        Commands::ListMethods => {
+            let methods = api_client.list_methods().await.map_err(|err| {
+                anyhow!("Node does not support method introspection (rpc.discover or list_methods): {}", err)
+            })?;
+            for method in &methods {
+                say!("{}", method);
+            }
+            say!("\nTotal methods: {}", methods.len());
+            output::emit(&json!({ "methods": methods }));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    
+    type Handler = Box<dyn Fn(&str, &Value) -> Result<Value> + Send + Sync>;
+    type Recorded = Arc<Mutex<Vec<Value>>>;
+    
+    // Answers every call from a canned handler and records the methods called.
+    struct MockTransport {
+        handler: Handler,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+    
+    #[async_trait]
+    impl RpcTransport for MockTransport {
+        async fn call(&self, method: &str, params: Value) -> Result<Value> {
+            self.calls.lock().unwrap().push(method.to_string());
+            (self.handler)(method, &params)
+        }
+        
+        async fn call_with_timeout(&self, method: &str, params: Value, _timeout: Duration) -> Result<Value> {
+            self.call(method, params).await
+        }
+        
+        // The handler sees the batch itself as a "batch" call, so it can
+        // refuse batches outright.
+        async fn call_batch(&self, calls: &[(&str, Value)]) -> Result<Vec<Result<Value>>> {
+            self.call("batch", json!(calls.len())).await?;
+            let mut results = Vec::with_capacity(calls.len());
+            for (method, params) in calls {
+                results.push(self.call(method, params.clone()).await);
+            }
+            Ok(results)
+        }
+    }
+    
+    fn method_not_found() -> anyhow::Error {
+        RpcError(json!({ "code": -32601, "message": "Method not found" })).into()
+    }
+    
+    fn mock_client(handler: impl Fn(&str, &Value) -> Result<Value> + Send + Sync + 'static) -> (ApiClient, Arc<Mutex<Vec<String>>>) {
+        mock_client_with(handler, RetryPolicy { retries: 0, max_backoff: Duration::ZERO, deadline: None })
+    }
+    
+    fn mock_client_with(
+        handler: impl Fn(&str, &Value) -> Result<Value> + Send + Sync + 'static,
+        retry: RetryPolicy,
+    ) -> (ApiClient, Arc<Mutex<Vec<String>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let transport = MockTransport { handler: Box::new(handler), calls: calls.clone() };
+        let client = ApiClient::with_transport(Box::new(transport), retry, Duration::from_secs(1), Duration::ZERO);
+        (client, calls)
+    }
+    
+    fn utxo(amount: u32, owner: Fr) -> Out {
+        Out { amount: Fr::from(amount), owner, data: Vec::new() }
+    }
+    
+    fn utxo_hex(utxo: &Out) -> String {
+        hex::encode(utxo.clone().enc().collect::<Vec<u8>>())
+    }
+    
+    const PAGE_SIZE: usize = 2;
+    const WALK: &[&str] = &["get_next_id_of_utxo_by_owner", "get_utxo", "get_utxo_amount"];
+    const PAGINATED: &[&str] = &["get_list_of_utxo_by_owner_paginated", "get_utxo", "get_utxo_amount"];
+    
+    // A node holding `utxos` (ids 1..) that implements only `methods`.
+    fn utxo_node(utxos: Vec<Out>, methods: &'static [&'static str]) -> impl Fn(&str, &Value) -> Result<Value> + Send + Sync {
+        let utxos: Vec<(Fr, Out)> = utxos
+            .into_iter()
+            .enumerate()
+            .map(|(i, utxo)| (Fr::from(i as u32 + 1), utxo))
+            .collect();
+        move |method: &str, params: &Value| {
+            if method == "batch" {
+                return Ok(Value::Null);
+            }
+            if !methods.contains(&method) {
+                return Err(method_not_found());
+            }
+            let find = |key: &str| utxos.iter().find(|(id, _)| params[key].as_str() == Some(id.to_hex().as_str()));
+            match method {
+                "get_next_id_of_utxo_by_owner" => {
+                    let position = match params["id"].as_str() {
+                        Some(id) if id == Fr::from(0u32).to_hex() => 0,
+                        _ => utxos.iter().position(|(id, _)| params["id"].as_str() == Some(id.to_hex().as_str())).map_or(utxos.len(), |i| i + 1),
+                    };
+                    Ok(json!(utxos.get(position).map_or_else(String::new, |(id, _)| id.to_hex())))
+                }
+                "get_utxo" => Ok(json!(find("id").map_or_else(String::new, |(_, utxo)| utxo_hex(utxo)))),
+                "get_list_of_utxo_by_owner_paginated" => {
+                    let start = utxos
+                        .iter()
+                        .position(|(id, _)| params["last_utxo_id"].as_str() == Some(id.to_hex().as_str()))
+                        .map_or(0, |i| i + 1);
+                    let page: Vec<&(Fr, Out)> = utxos.iter().skip(start).take(PAGE_SIZE).collect();
+                    Ok(json!({
+                        "utxos": page.iter().map(|(id, utxo)| json!({ "id": id.to_hex(), "utxo": utxo_hex(utxo) })).collect::<Vec<_>>(),
+                        "last_utxo_id": page.last().map(|(id, _)| id.to_hex()),
+                    }))
+                }
+                "get_utxo_amount" => find("id")
+                    .map(|(_, utxo)| json!(utxo.amount.to_hex()))
+                    .ok_or_else(|| anyhow!("unknown UTXO")),
+                _ => Err(anyhow!("unexpected call to {}", method)),
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn retries_stop_at_the_deadline() {
+        let retry = RetryPolicy {
+            retries: 100,
+            max_backoff: Duration::from_millis(50),
+            deadline: Some(Instant::now() + Duration::from_millis(300)),
+        };
+        let (client, calls) = mock_client_with(
+            |_, _| Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused").into()),
+            retry,
+        );
+        
+        let started = Instant::now();
+        assert!(client.get_balance("00").await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let attempts = calls.lock().unwrap().len();
+        assert!(attempts > 1 && attempts < 100, "made {} attempts", attempts);
+    }
+    
+    #[tokio::test]
+    async fn deadline_bounds_wait_for_tx() {
+        let retry = RetryPolicy { retries: 0, max_backoff: Duration::ZERO, deadline: Some(Instant::now()) };
+        let (client, _) = mock_client_with(|method, _| match method {
+            "get_tail" => Ok(json!("01")),
+            _ => Ok(json!(utxo_hex(&utxo(1, Fr::from(1u32))))),
+        }, retry);
+        
+        let started = Instant::now();
+        let confirmed = wait_for_tx(&client, Fr::from(9u32), &[Fr::from(1u32)], Duration::from_secs(60)).await;
+        assert!(!confirmed);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+    
+    #[tokio::test]
+    async fn wait_for_tx_polls_through_node_errors() {
+        let polls = AtomicU64::new(0);
+        let (client, _) = mock_client(move |method, _| match method {
+            "get_tail" if polls.fetch_add(1, Ordering::Relaxed) == 0 => {
+                Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset").into())
+            }
+            "get_tail" => Ok(json!("02")),
+            _ => Ok(json!("")),
+        });
+        
+        assert!(wait_for_tx(&client, Fr::from(9u32), &[Fr::from(1u32)], Duration::from_secs(10)).await);
+    }
+    
+    #[tokio::test]
+    async fn light_amount_query_survives_unrelated_errors() {
+        let owner = Fr::from(7u32);
+        let node = utxo_node(vec![utxo(5, owner)], &["get_utxo", "get_utxo_amount"]);
+        let (client, calls) = mock_client(node);
+        
+        let amounts = fetch_utxo_amounts(&client, &[Fr::from(9u32), Fr::from(1u32)], 1).await;
+        assert!(amounts[0].is_err());
+        assert_eq!(amounts[1].as_ref().unwrap(), &Fr::from(5u32));
+        assert!(client.light_utxo_query.load(Ordering::Relaxed));
+        assert!(!calls.lock().unwrap().iter().any(|method| method == "get_utxo"));
+    }
+    
+    #[tokio::test]
+    async fn light_amount_query_falls_back_when_missing() {
+        let owner = Fr::from(7u32);
+        let (client, calls) = mock_client(utxo_node(vec![utxo(5, owner)], &["get_utxo"]));
+        
+        let amounts = fetch_utxo_amounts(&client, &[Fr::from(1u32)], 1).await;
+        assert_eq!(amounts[0].as_ref().unwrap(), &Fr::from(5u32));
+        assert!(!client.light_utxo_query.load(Ordering::Relaxed));
+        let amounts = fetch_utxo_amounts(&client, &[Fr::from(1u32)], 1).await;
+        assert_eq!(amounts[0].as_ref().unwrap(), &Fr::from(5u32));
+        assert_eq!(*calls.lock().unwrap(), vec!["batch", "get_utxo_amount", "batch", "get_utxo", "batch", "get_utxo"]);
+    }
+    
+    fn batch_request(method: &str, id: u64) -> JsonRpcRequest {
+        JsonRpcRequest { jsonrpc: "2.0".to_string(), method: method.to_string(), params: json!({}), id }
+    }
+    
+    #[test]
+    fn batch_reply_is_matched_by_id_not_position() {
+        let requests = vec![batch_request("get_utxo", 1), batch_request("get_utxo", 2), batch_request("get_utxo", 3)];
+        let body = json!([
+            { "jsonrpc": "2.0", "result": "c", "id": 3 },
+            { "jsonrpc": "2.0", "error": { "code": -32000, "message": "gone" }, "id": 2 },
+            { "jsonrpc": "2.0", "result": "a", "id": 1 },
+        ]).to_string();
+        
+        let results = parse_batch_reply(&requests, 200, &body).unwrap();
+        assert_eq!(results[0].as_ref().unwrap(), &json!("a"));
+        assert!(results[1].as_ref().unwrap_err().is::<RpcError>());
+        assert_eq!(results[2].as_ref().unwrap(), &json!("c"));
+    }
+    
+    #[test]
+    fn batch_reply_without_an_answer_fails_only_that_slot() {
+        let requests = vec![batch_request("get_utxo", 1), batch_request("get_utxo", 2)];
+        let body = json!([{ "jsonrpc": "2.0", "result": "b", "id": 2 }]).to_string();
+        
+        let results = parse_batch_reply(&requests, 200, &body).unwrap();
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().unwrap(), &json!("b"));
+    }
+    
+    #[test]
+    fn non_array_batch_reply_means_no_batch_support() {
+        let requests = vec![batch_request("get_utxo", 1)];
+        let body = json!({ "jsonrpc": "2.0", "error": { "code": -32600, "message": "Invalid Request" }, "id": null }).to_string();
+        
+        let err = parse_batch_reply(&requests, 200, &body).unwrap_err();
+        assert!(is_batch_unsupported(&err));
+    }
+    
+    #[tokio::test]
+    async fn transient_batch_failure_keeps_batching() {
+        let owner = Fr::from(7u32);
+        let node = utxo_node(vec![utxo(5, owner)], &["get_utxo"]);
+        let failed = AtomicBool::new(false);
+        let (client, _) = mock_client(move |method, params| {
+            if method == "batch" && !failed.swap(true, Ordering::Relaxed) {
+                return Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset").into());
+            }
+            node(method, params)
+        });
+        
+        assert!(fetch_utxo_hexes(&client, &[Fr::from(1u32)]).await[0].is_err());
+        assert!(client.batch_supported.load(Ordering::Relaxed));
+        assert_eq!(fetch_utxo_hexes(&client, &[Fr::from(1u32)]).await[0].as_ref().unwrap(), &utxo_hex(&utxo(5, owner)));
+    }
+    
+    #[tokio::test]
+    async fn refused_batch_falls_back_to_single_calls() {
+        let owner = Fr::from(7u32);
+        let node = utxo_node(vec![utxo(5, owner), utxo(6, owner)], &["get_utxo"]);
+        let (client, calls) = mock_client(move |method, params| match method {
+            "batch" => Err(BatchUnsupported { snippet: String::new() }.into()),
+            _ => node(method, params),
+        });
+        
+        let fetched = fetch_utxo_hexes(&client, &[Fr::from(2u32), Fr::from(1u32)]).await;
+        assert_eq!(fetched[0].as_ref().unwrap(), &utxo_hex(&utxo(6, owner)));
+        assert_eq!(fetched[1].as_ref().unwrap(), &utxo_hex(&utxo(5, owner)));
+        assert!(!client.batch_supported.load(Ordering::Relaxed));
+        assert_eq!(*calls.lock().unwrap(), vec!["batch", "get_utxo", "get_utxo"]);
+    }
+    
+    fn summary(utxos: &[(Fr, Out)]) -> Vec<(Fr, Fr, Fr)> {
+        utxos.iter().map(|(id, utxo)| (*id, utxo.amount, utxo.owner)).collect()
+    }
+    
+    #[tokio::test]
+    async fn paginated_only_node_yields_the_same_utxos_as_the_walk() {
+        let owner = Fr::from(7u32);
+        let utxos = vec![utxo(5, owner), utxo(20, owner), utxo(1, Fr::from(8u32)), utxo(40, owner), utxo(2, owner)];
+        let (walk_client, _) = mock_client(utxo_node(utxos.clone(), WALK));
+        let (paged_client, calls) = mock_client(utxo_node(utxos, PAGINATED));
+        let account: HexString = owner.to_hex().parse().unwrap();
+        
+        let walked = fetch_owned_utxos(&walk_client, &account, owner, UtxoDetail::Full).await.unwrap();
+        let paged = fetch_owned_utxos(&paged_client, &account, owner, UtxoDetail::Full).await.unwrap();
+        assert_eq!(summary(&paged), summary(&walked));
+        assert_eq!(paged.len(), 4);
+        assert!(calls.lock().unwrap().iter().any(|method| method == "get_list_of_utxo_by_owner_paginated"));
+        
+        let candidates = gather_transfer_utxos(&paged_client, &account, &[Fr::from(2u32)], 1).await.unwrap();
+        let ids: Vec<Fr> = candidates.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![Fr::from(1u32), Fr::from(4u32), Fr::from(5u32)]);
+    }
+    
+    #[tokio::test]
+    async fn utxos_repeated_across_pages_are_counted_once() {
+        let owner = Fr::from(7u32);
+        let entry = |id: u32, amount: u32| json!({ "id": Fr::from(id).to_hex(), "utxo": utxo_hex(&utxo(amount, owner)) });
+        let pages = [
+            json!({ "utxos": [entry(1, 5), entry(2, 20)], "last_utxo_id": Fr::from(2u32).to_hex() }),
+            // Repeats id 1 mid-page as well as id 2 at the boundary.
+            json!({ "utxos": [entry(2, 20), entry(3, 40), entry(1, 5)], "last_utxo_id": Fr::from(3u32).to_hex() }),
+            json!({ "utxos": [] }),
+        ];
+        let page = AtomicU64::new(0);
+        let (client, _) = mock_client(move |method, _| match method {
+            "get_list_of_utxo_by_owner_paginated" => Ok(pages[page.fetch_add(1, Ordering::Relaxed) as usize].clone()),
+            _ => Err(method_not_found()),
+        });
+        let account: HexString = owner.to_hex().parse().unwrap();
+        
+        let utxos = fetch_owned_utxos(&client, &account, owner, UtxoDetail::Full).await.unwrap();
+        let ids: Vec<Fr> = utxos.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![Fr::from(1u32), Fr::from(2u32), Fr::from(3u32)]);
+        let total = utxos.iter().fold(Fr::from(0u32), |total, (_, utxo)| total + utxo.amount);
+        assert_eq!(total, Fr::from(65u32));
+    }
+    
+    #[tokio::test]
+    async fn paginated_listing_without_ids_cannot_be_spent_from() {
+        let owner = Fr::from(7u32);
+        let hex = utxo_hex(&utxo(5, owner));
+        let (client, _) = mock_client(move |method, params| match method {
+            "get_list_of_utxo_by_owner_paginated" if params["last_utxo_id"] == json!(Fr::from(0u32).to_hex()) => {
+                Ok(json!({ "utxos": [hex.clone()], "last_utxo_id": "01" }))
+            }
+            "get_list_of_utxo_by_owner_paginated" => Ok(json!({ "utxos": [] })),
+            _ => Err(method_not_found()),
+        });
+        let account: HexString = owner.to_hex().parse().unwrap();
+        
+        assert_eq!(fetch_all_utxos(&client, &account).await.unwrap().len(), 1);
+        assert!(fetch_owned_utxos(&client, &account, owner, UtxoDetail::Full).await.is_err());
+    }
+    
+    // Runs a consolidation plan the way run_consolidate does, minus proving
+    // and submitting: each Tx's inputs leave the set and its outputs join it
+    // under fresh ids. Returns the executed step count and fee paid.
+    fn execute_plan(plan: &ConsolidationPlan, utxos: &mut Vec<(Fr, Out)>, own: Fr) -> (usize, Amount) {
+        let mut produced = Vec::new();
+        let mut paid = Amount::zero();
+        for (i, step) in plan.steps.iter().enumerate() {
+            let selection = resolve_merge_inputs(step, utxos, &produced).unwrap();
+            let inputs = selection.total().unwrap();
+            let tx = construct_transfer_tx(&selection, own, step.merged.0, plan.fee, own).unwrap();
+            let before = utxos.clone();
+            utxos.retain(|(id, _)| *id != tx.ix && *id != tx.iy);
+            utxos.push((Fr::from(1000 + 2 * i as u32), tx.ox.clone()));
+            utxos.push((Fr::from(1001 + 2 * i as u32), tx.oy.clone()));
+            let outputs = Amount(tx.ox.amount).checked_add(Amount(tx.oy.amount)).unwrap();
+            paid = paid.checked_add(inputs.checked_sub(outputs).unwrap()).unwrap();
+            produced.push(find_merge_output(&before, utxos, step.merged));
+        }
+        (plan.steps.len(), paid)
+    }
+    
+    #[test]
+    fn consolidation_plan_matches_its_execution() {
+        let own = Fr::from(7u32);
+        let mut utxos: Vec<(Fr, Out)> = [4u32, 9, 0, 6, 30, 2]
+            .iter()
+            .enumerate()
+            .map(|(i, amount)| (Fr::from(i as u32 + 1), utxo(*amount, own)))
+            .collect();
+        let plan = ConsolidationPlan::new(&utxos, Fr::from(3u32), None).unwrap();
+        assert_eq!(plan.initial_count, 5);
+        assert_eq!(plan.steps.len(), 4);
+        assert_eq!(plan.final_count, 1);
+        
+        let (steps, paid) = execute_plan(&plan, &mut utxos, own);
+        assert_eq!(steps, plan.steps.len());
+        assert_eq!(paid.0, plan.total_fee.0);
+        assert_eq!(paid.0, Fr::from(12u32));
+        assert_eq!(spendable_count(&utxos), plan.final_count);
+        assert_eq!(utxos.iter().fold(Fr::from(0u32), |total, (_, utxo)| total + utxo.amount), Fr::from(39u32));
+    }
+    
+    #[test]
+    fn consolidation_plan_stops_when_the_smallest_pair_cannot_pay_the_fee() {
+        let own = Fr::from(7u32);
+        let utxos = vec![(Fr::from(1u32), utxo(1, own)), (Fr::from(2u32), utxo(1, own)), (Fr::from(3u32), utxo(50, own))];
+        let plan = ConsolidationPlan::new(&utxos, Fr::from(3u32), None).unwrap();
+        assert!(plan.steps.is_empty());
+        assert!(plan.fee_limited);
+        assert_eq!(plan.final_count, 3);
+    }
+    
+    #[test]
+    fn decode_tx_requires_an_exact_encoding() {
+        let tx = construct_tx(Fr::from(1u32), Fr::from(2u32), (Fr::from(7u32), Fr::from(5u32)), (Fr::from(7u32), Fr::from(0u32)));
+        let bytes: Vec<u8> = tx.clone().enc().collect();
+        assert!(decode_tx(&hex::encode(&bytes)).unwrap().hash() == tx.hash());
+        
+        let mut padded = bytes;
+        padded.push(0);
+        assert!(decode_tx(&hex::encode(&padded)).is_err());
+    }
+    
+    #[test]
+    fn archive_keeps_the_submitted_bytes_unchanged() {
+        let tx = construct_tx(Fr::from(1u32), Fr::from(2u32), (Fr::from(7u32), Fr::from(5u32)), (Fr::from(7u32), Fr::from(0u32)));
+        let submitted = [9u8, 8, 7, 6];
+        let dir = std::env::temp_dir().join(format!("wallet-archive-test-{}", std::process::id()));
+        let path = archive_submission(&dir, &tx, &submitted, false).unwrap();
+        let record = read_archive(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        
+        assert_eq!(record["wp_tx"], hex::encode(submitted));
+        assert_eq!(record["wire_version"], codec::WIRE_VERSION);
+        assert!(codec::check_version(&record).is_ok());
+        assert!(codec::check_version(&json!({ "wire_version": 2 })).is_err());
+    }
+    
+    #[test]
+    fn vk_cache_is_written_reused_and_refreshed_on_mismatch() {
+        let path = std::env::temp_dir().join(format!("wallet-vk-test-{}", std::process::id()));
+        let encoded = |vk: Vk| hex::encode(vk.enc().collect::<Vec<u8>>());
+        
+        assert_eq!(encoded(cached_vk(&path, "0a0b0c").unwrap()), "0a0b0c");
+        assert_eq!(fs::read(&path).unwrap(), [0x0a, 0x0b, 0x0c]);
+        assert_eq!(encoded(cached_vk(&path, "0x0A0B0C").unwrap()), "0a0b0c");
+        
+        assert_eq!(encoded(cached_vk(&path, "0d0e").unwrap()), "0d0e");
+        assert_eq!(fs::read(&path).unwrap(), [0x0d, 0x0e]);
+        fs::remove_file(&path).unwrap();
+    }
+    
+    #[test]
+    fn required_amount_rejects_overflow() {
+        let near_modulus = Fr::from(0u32) - Fr::from(1u32);
+        assert!(required_amount(Fr::from(5u32), Some((Fr::from(9u32), Fr::from(3u32)))).unwrap() == Fr::from(8u32));
+        assert!(required_amount(near_modulus, None).unwrap() == near_modulus);
+        assert!(required_amount(near_modulus, Some((Fr::from(9u32), Fr::from(1u32)))).is_err());
+    }
+    
+    #[test]
+    fn unselectable_amount_is_explained_by_its_cause() {
+        let utxos = numbered(&[10, 10, 10, 30], Fr::from(7u32));
+        let fee = Fr::from(3u32);
+        let message = |amount: u32| explain_unselectable(&utxos, Fr::from(amount), fee).to_string();
+        
+        assert!(message(45).contains("requires 3 inputs"));
+        assert!(message(30).contains("changed on the node"));
+        assert!(message(100).contains("Insufficient balance"));
+    }
+    
+    #[test]
+    fn balance_json_carries_the_amount_envelope() {
+        assert_eq!(
+            balance_report(Fr::from(1000u32)),
+            json!({ "balance": { "hex": Fr::from(1000u32).to_hex(), "decimal": "1000", "decimals": 0 } })
+        );
+    }
+    
+    #[test]
+    fn keystore_decryptions_in_one_run_prompt_once() {
+        let secret = Secret::new(Fr::from(42u32));
+        let record = keystore::encrypt("account", &secret, "correct horse").unwrap();
+        let path = Path::new("account.json");
+        let args = PassphraseArgs { passphrase: None, no_cache_passphrase: false };
+        
+        let mut prompts = 0;
+        let decrypt = |answer: &'static str, prompts: &mut usize| {
+            let passphrase = args.read_with(false, &mut |_| {
+                *prompts += 1;
+                Ok(answer.to_string())
+            });
+            keystore::decrypt(&record, path, &passphrase.unwrap()).inspect_err(|_| passphrase::forget())
+        };
+        
+        passphrase::forget();
+        assert!(decrypt("wrong", &mut prompts).is_err());
+        assert!(decrypt("correct horse", &mut prompts).unwrap().expose() == secret.expose());
+        assert!(decrypt("ignored", &mut prompts).unwrap().expose() == secret.expose());
+        assert_eq!(prompts, 2);
+        passphrase::forget();
+        assert!(passphrase::cached().is_none());
+    }
+    
+    #[tokio::test]
+    async fn permissionless_transfer_needs_no_secret_and_returns_change_to_from() {
+        let from = Fr::from(7u32);
+        let to = Fr::from(9u32);
+        wallet_prover_ffi::stub::install(&from.to_hex());
+        
+        let submitted = Arc::new(Mutex::new(Vec::new()));
+        let node = utxo_node(vec![utxo(40, from), utxo(25, from)], WALK);
+        let record = submitted.clone();
+        let (api, _) = mock_client(move |method, params| match method {
+            "submit_transaction" => {
+                record.lock().unwrap().push(params["tx"].as_str().unwrap().to_string());
+                Ok(Value::Null)
+            }
+            _ => node(method, params),
+        });
+        let cli = Cli::parse_from([
+            "wallet",
+            "transfer-permissionless",
+            "--from",
+            &from.to_hex(),
+            "--to",
+            &to.to_hex(),
+            "--amount",
+            &Fr::from(30u32).to_hex(),
+            "--yes",
+        ]);
+        let Commands::TransferPermissionless { args } = &cli.command else { unreachable!() };
+        
+        let (tx, _) = run_transfer(&api, &cli, args, Authority::Permissionless, &[]).await.unwrap();
+        assert_eq!(wallet_prover_ffi::stub::calls(), ["GenerateProofPermissionless"]);
+        assert!(tx.ix == Fr::from(1u32) && tx.iy == Fr::from(0u32));
+        assert!(tx.ox.owner == to && tx.ox.amount == Fr::from(30u32));
+        assert!(tx.oy.owner == from && tx.oy.amount == Fr::from(7u32));
+        
+        let submitted = submitted.lock().unwrap();
+        assert_eq!(submitted.len(), 1);
+        let bytes = hex::decode(&submitted[0]).unwrap();
+        let mut iter = bytes.iter().copied();
+        let Wp { vk, proof, val } = Wp::<Tx>::dec(&mut iter).unwrap();
+        assert!(iter.next().is_none());
+        assert_eq!(hex::encode(vk.enc().collect::<Vec<u8>>()), wallet_prover_ffi::stub::VK_HEX);
+        assert_eq!(hex::encode(proof.enc().collect::<Vec<u8>>()), wallet_prover_ffi::stub::PROOF_HEX);
+        assert!(val.hash() == tx.hash() && val.oy.owner == from);
+    }
+    
+    // Fr on the wire, as the node sends ids and amounts: 32 bytes,
+    // big-endian. A dependency that flips the byte order fails here first.
+    const BLS12_381_MODULUS: &str = "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001";
+    
+    fn known_fr_vectors() -> Vec<(Fr, String)> {
+        vec![
+            // Also where the UTXO walk starts.
+            (Fr::from(0u32), "00".repeat(32)),
+            (Fr::from(1u32), format!("{:0>64}", "01")),
+            (Fr::from(8u32), format!("{:0>64}", "08")),
+            (Fr::from(0x0102u32), format!("{:0>64}", "0102")),
+            (Fr::from(u64::MAX), format!("{:0>64}", "ffffffffffffffff")),
+            (Fr::from(0u32) - Fr::from(1u32), "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000000".to_string()),
+        ]
+    }
+    
+    #[test]
+    fn fr_hex_matches_known_wire_vectors() {
+        for (value, hex) in known_fr_vectors() {
+            assert_eq!(value.to_hex(), hex);
+            assert!(<Fr as HexConverter>::from_hex(hex.parse().unwrap()).unwrap() == value, "{}", hex);
+            assert_eq!(*Secret::new(value).to_hex(), hex);
+        }
+    }
+    
+    #[test]
+    fn fr_bytes_match_known_wire_vectors() {
+        for (value, hex) in known_fr_vectors() {
+            let bytes = hex::decode(&hex).unwrap();
+            assert_eq!(value.enc().collect::<Vec<u8>>(), bytes, "{}", hex);
+            assert!(Fr::dec(&mut bytes.into_iter()).unwrap() == value, "{}", hex);
+        }
+    }
+    
+    #[test]
+    fn fr_at_the_modulus_is_not_a_canonical_address() {
+        assert!(parse_address(&BLS12_381_MODULUS.parse().unwrap()).is_err());
+        assert!(artifact::fr("id", BLS12_381_MODULUS).is_err());
+    }
+    
+    #[test]
+    fn transfer_tx_refuses_inputs_that_do_not_cover_amount_and_fee() {
+        let own = Fr::from(7u32);
+        let selection = Selection::from_inputs(numbered(&[10, 20], own)).unwrap();
+        let build = |amount: u32| construct_transfer_tx(&selection, Fr::from(9u32), Fr::from(amount), Fr::from(3u32), own);
+        
+        let err = build(28).unwrap_err().to_string();
+        assert!(err.contains("insufficient input: need 31, have 30"), "{}", err);
+        let exact = build(27).unwrap();
+        assert!(exact.oy.amount == Fr::from(0u32) && exact.oy.owner == own);
+        
+        let near_modulus = Fr::from(0u32) - Fr::from(1u32);
+        assert!(build(0).is_ok());
+        assert!(construct_transfer_tx(&selection, Fr::from(9u32), near_modulus, Fr::from(3u32), own).is_err());
+    }
+    
+    fn select_with(strategy: CoinSelection, seed: Option<u64>) -> Vec<Fr> {
+        let mut utxos = numbered(&[20, 5, 40, 8, 14], Fr::from(7u32));
+        order_candidates(&mut utxos, strategy, seed);
+        let selection = select_utxos(utxos, Fr::from(10u32), Fr::from(3u32), strategy).unwrap();
+        selection.inputs().iter().map(|(id, _)| *id).collect()
+    }
+    
+    #[test]
+    fn each_coin_selection_strategy_picks_its_own_inputs() {
+        let ids = |ids: &[u32]| ids.iter().map(|id| Fr::from(*id)).collect::<Vec<_>>();
+        // 13 is needed: the first UTXO that covers it, the largest, the
+        // smallest that covers it, and the exact 8 + 5 pair.
+        assert!(select_with(CoinSelection::FirstFit, None) == ids(&[1]));
+        assert!(select_with(CoinSelection::LargestFirst, None) == ids(&[3]));
+        assert!(select_with(CoinSelection::SmallestFirst, None) == ids(&[5]));
+        assert!(select_with(CoinSelection::BranchAndBound, None) == ids(&[4, 2]));
+        
+        let seeded = select_with(CoinSelection::Random, Some(42));
+        assert!(seeded == select_with(CoinSelection::Random, Some(42)));
+        assert!(!seeded.is_empty() && seeded.len() <= 2);
+    }
+    
+    #[test]
+    fn sweep_spends_the_two_largest_utxos_with_zero_change() {
+        let own = Fr::from(7u32);
+        let fee = parse_fee(None).unwrap();
+        assert!(fee == Fr::from(DEFAULT_FEE));
+        
+        let (selection, sendable) = max_sendable(&numbered(&[12, 30, 0, 5, 18], own), fee).unwrap();
+        assert!(sendable == Amount(Fr::from(45u32)));
+        let tx = construct_transfer_tx(&selection, Fr::from(9u32), sendable.0, fee, own).unwrap();
+        assert!(tx.ix == Fr::from(2u32) && tx.iy == Fr::from(5u32));
+        assert!(tx.ox.amount == Fr::from(45u32) && tx.oy.amount == Fr::from(0u32));
+        
+        let spent: Vec<(Fr, Fr)> = selection.inputs().iter().map(|(id, utxo)| (*id, utxo.amount)).collect();
+        assert!(check_value_conserved(&spent, &tx, fee).is_ok());
+        assert!(max_sendable(&numbered(&[1, 1], own), fee).is_none());
+    }
+    
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wallet-{}-{}", name, std::process::id()))
+    }
+    
+    #[test]
+    fn created_keystore_is_read_back_and_checked() {
+        let secret = Secret::new(Fr::from(42u32));
+        let account = generate_address(&secret).unwrap();
+        let path = temp_path("create-check.json");
+        let write = |record: &Value| fs::write(&path, serde_json::to_vec(record).unwrap()).unwrap();
+        
+        let record = keystore::encrypt(&account, &secret, "pass").unwrap();
+        write(&record);
+        assert!(verify_stored_account(&path, "pass", &account).is_ok());
+        
+        let mut corrupted = record.clone();
+        let ciphertext = corrupted["ciphertext"].as_str().unwrap().to_string();
+        corrupted["ciphertext"] = json!(format!("{}{}", if ciphertext.starts_with('0') { "1" } else { "0" }, &ciphertext[1..]));
+        write(&corrupted);
+        assert!(verify_stored_account(&path, "pass", &account).is_err());
+        
+        write(&keystore::encrypt(&account, &Secret::new(Fr::from(43u32)), "pass").unwrap());
+        let err = verify_stored_account(&path, "pass", &account).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(err.contains("decrypts to the secret of"), "{}", err);
+    }
+    
+    fn numbered(amounts: &[u32], own: Fr) -> Vec<(Fr, Out)> {
+        amounts.iter().enumerate().map(|(i, amount)| (Fr::from(i as u32 + 1), utxo(*amount, own))).collect()
+    }
+    
+    fn has_coin(utxos: &[(Fr, Out)], amount: u32) -> bool {
+        utxos.iter().any(|(_, utxo)| utxo.amount == Fr::from(amount))
+    }
+    
+    #[test]
+    fn consolidation_target_merges_into_a_coin_of_that_size() {
+        let own = Fr::from(7u32);
+        let mut utxos = numbered(&[10, 40, 25, 30], own);
+        let plan = ConsolidationPlan::new(&utxos, Fr::from(3u32), Some(Fr::from(80u32))).unwrap();
+        // 40 + 30 -> 67, then 67 + 25 -> 80 plus 9 change.
+        assert_eq!(plan.steps.len(), 2);
+        
+        let (steps, paid) = execute_plan(&plan, &mut utxos, own);
+        assert_eq!((steps, paid.0), (2, plan.total_fee.0));
+        assert!(has_coin(&utxos, 80));
+        assert!(has_coin(&utxos, 9));
+        assert_eq!(spendable_count(&utxos), plan.final_count);
+    }
+    
+    #[test]
+    fn consolidation_target_splits_the_smallest_sufficient_coin() {
+        let own = Fr::from(7u32);
+        let mut utxos = numbered(&[100, 20, 60], own);
+        let plan = ConsolidationPlan::new(&utxos, Fr::from(3u32), Some(Fr::from(50u32))).unwrap();
+        assert_eq!(plan.steps.len(), 1);
+        assert!(plan.steps[0].inputs == vec![(MergeInput::Utxo(Fr::from(3u32)), Amount(Fr::from(60u32)))]);
+        
+        execute_plan(&plan, &mut utxos, own);
+        assert!(has_coin(&utxos, 50) && has_coin(&utxos, 7) && has_coin(&utxos, 100));
+    }
+    
+    #[test]
+    fn consolidation_target_that_already_exists_needs_no_steps() {
+        let plan = ConsolidationPlan::new(&numbered(&[5, 50], Fr::from(7u32)), Fr::from(3u32), Some(Fr::from(50u32))).unwrap();
+        assert!(plan.steps.is_empty());
+    }
+    
+    #[test]
+    fn consolidation_target_beyond_the_balance_is_rejected() {
+        let utxos = numbered(&[10, 20, 30], Fr::from(7u32));
+        // 60 in total, but reaching 57 takes two merges and 6 in fees.
+        assert!(ConsolidationPlan::new(&utxos, Fr::from(3u32), Some(Fr::from(57u32))).is_err());
+        assert!(ConsolidationPlan::new(&utxos, Fr::from(3u32), Some(Fr::from(54u32))).is_ok());
+    }
+    
+    #[test]
+    fn consolidation_stops_if_a_planned_input_changed() {
+        let own = Fr::from(7u32);
+        let utxos = vec![(Fr::from(1u32), utxo(5, own)), (Fr::from(2u32), utxo(6, own))];
+        let plan = ConsolidationPlan::new(&utxos, Fr::from(3u32), None).unwrap();
+        let changed = vec![(Fr::from(1u32), utxo(5, own)), (Fr::from(2u32), utxo(7, own))];
+        assert!(resolve_merge_inputs(&plan.steps[0], &changed, &[]).is_err());
+        assert!(resolve_merge_inputs(&plan.steps[0], &utxos[..1], &[]).is_err());
+    }
+    
+    #[tokio::test]
+    async fn transfer_gathers_amounts_then_fetches_selected_inputs() {
+        let owner = Fr::from(7u32);
+        let node = utxo_node(vec![utxo(5, owner), utxo(20, owner), utxo(40, owner)], WALK);
+        let (client, calls) = mock_client(node);
+        let from: HexString = owner.to_hex().parse().unwrap();
+        
+        let candidates = gather_transfer_utxos(&client, &from, &[Fr::from(3u32)], 4).await.unwrap();
+        let amounts: Vec<Fr> = candidates.iter().map(|(_, utxo)| utxo.amount).collect();
+        assert_eq!(amounts, vec![Fr::from(5u32), Fr::from(20u32)]);
+        assert!(!calls.lock().unwrap().iter().any(|method| method == "get_utxo"));
+        
+        let selection = select_owned_utxos(&client, candidates, Fr::from(15u32), Fr::from(3u32), CoinSelection::FirstFit, owner)
+            .await
+            .unwrap()
+            .expect("20 covers 15 plus the fee");
+        let inputs = selection.inputs();
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].0, Fr::from(2u32));
+        assert_eq!((inputs[0].1.amount, inputs[0].1.owner), (Fr::from(20u32), owner));
+        assert_eq!(calls.lock().unwrap().iter().filter(|method| *method == "get_utxo").count(), 1);
+    }
+
+    #[test]
+    fn backoff_doubles_then_caps() {
+        assert_eq!(backoff(0), Duration::from_millis(250));
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(5), Duration::from_millis(8_000));
+        assert_eq!(backoff(7), Duration::from_millis(32_000));
+        assert_eq!(backoff(63), Duration::from_millis(u64::MAX));
+        assert_eq!(backoff(usize::MAX), Duration::from_millis(u64::MAX));
+        
+        let policy = |max_backoff_ms| RetryPolicy { retries: 100, max_backoff: Duration::from_millis(max_backoff_ms), deadline: None };
+        let default = policy(MAX_BACKOFF_MS);
+        assert_eq!(default.next_backoff(5), Some(Duration::from_millis(8_000)));
+        assert_eq!(default.next_backoff(6), Some(Duration::from_millis(MAX_BACKOFF_MS)));
+        assert_eq!(default.next_backoff(99), Some(Duration::from_millis(MAX_BACKOFF_MS)));
+        // A --max-backoff-ms above the default is honoured, not cut to it.
+        assert_eq!(policy(60_000).next_backoff(7), Some(Duration::from_millis(32_000)));
+        assert_eq!(policy(60_000).next_backoff(8), Some(Duration::from_millis(60_000)));
+        assert_eq!(policy(60_000).next_backoff(99), Some(Duration::from_millis(60_000)));
+    }
+    
+    fn fast_retry(retries: usize) -> RetryPolicy {
+        RetryPolicy { retries, max_backoff: Duration::from_millis(1), deadline: None }
+    }
+    
+    fn refused() -> anyhow::Error {
+        std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused").into()
+    }
+    
+    // A node that loses the reply to the first submit_transaction. Whether the
+    // transaction actually landed shows in get_utxo: `landed` answers empty for
+    // the inputs. Returns the params of every submit.
+    fn lossy_submit_node(landed: bool) -> (impl Fn(&str, &Value) -> Result<Value> + Send + Sync, Recorded) {
+        let submits = Arc::new(Mutex::new(Vec::new()));
+        let record = submits.clone();
+        let handler = move |method: &str, params: &Value| {
+            match method {
+                "submit_transaction" => {
+                    let mut submits = record.lock().unwrap();
+                    submits.push(params.clone());
+                    if submits.len() == 1 { Err(refused()) } else { Ok(Value::Null) }
+                }
+                "get_utxo" if landed => Ok(json!("")),
+                "get_utxo" => Ok(json!(utxo_hex(&utxo(5, Fr::from(7u32))))),
+                _ => Err(anyhow!("unexpected call to {}", method)),
+            }
+        };
+        (handler, submits)
+    }
+    
+    #[tokio::test]
+    async fn lost_submit_reply_with_spent_inputs_is_already_known() {
+        let (handler, submits) = lossy_submit_node(true);
+        let (client, _) = mock_client_with(handler, fast_retry(3));
+        let submission = client.submit_idempotent("abcd", "key", &[Fr::from(1u32), Fr::from(2u32)]).await.unwrap();
+        assert!(submission == Submission::AlreadyKnown);
+        assert_eq!(submits.lock().unwrap().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn lost_submit_reply_with_unspent_inputs_is_resubmitted() {
+        let (handler, submits) = lossy_submit_node(false);
+        let (client, _) = mock_client_with(handler, fast_retry(3));
+        let submission = client.submit_idempotent("abcd", "key", &[Fr::from(1u32)]).await.unwrap();
+        assert!(submission == Submission::New);
+        assert_eq!(submits.lock().unwrap().len(), 2);
+    }
+    
+    #[test]
+    fn broken_construction_does_not_conserve_value() {
+        let own = Fr::from(7u32);
+        let selection = Selection::from_inputs(numbered(&[10, 20], own)).unwrap();
+        let spent: Vec<(Fr, Fr)> = selection.inputs().iter().map(|(id, utxo)| (*id, utxo.amount)).collect();
+        let fee = Fr::from(3u32);
+        let tx = construct_transfer_tx(&selection, Fr::from(9u32), Fr::from(12u32), fee, own).unwrap();
+        assert!(check_value_conserved(&spent, &tx, fee).is_ok());
+        
+        let mut inflated = tx.clone();
+        inflated.oy.amount = inflated.oy.amount + Fr::from(1u32);
+        let err = check_value_conserved(&spent, &inflated, fee).unwrap_err().to_string();
+        assert!(err.contains("tx does not conserve value"), "{}", err);
+        assert!(check_value_conserved(&spent, &tx, Fr::from(2u32)).is_err());
+    }
+    
+    #[tokio::test]
+    async fn transient_failures_are_retried_until_the_node_answers() {
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+        let (client, calls) = mock_client_with(
+            move |_, _| match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(refused()),
+                _ => Ok(json!("2a")),
+            },
+            fast_retry(5),
+        );
+        assert_eq!(client.get_balance("00").await.unwrap(), "2a");
+        assert_eq!(calls.lock().unwrap().len(), 3);
+    }
+    
+    #[tokio::test]
+    async fn rpc_errors_are_not_retried() {
+        let (client, calls) = mock_client_with(
+            |_, _| Err(RpcError(json!({ "code": -32602, "message": "Invalid params" })).into()),
+            fast_retry(5),
+        );
+        assert!(client.get_balance("00").await.unwrap_err().is::<RpcError>());
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn walk_collects_every_id_past_the_old_iteration_cap() {
+        let owner = Fr::from(7u32);
+        let (client, _) = mock_client(utxo_node((0..250).map(|_| utxo(1, owner)).collect(), WALK));
+        let ids = fetch_all_utxo_ids(&client, &owner.to_hex().parse().unwrap()).await.unwrap();
+        assert_eq!(ids.len(), 250);
+        assert!(ids.iter().zip(1u32..).all(|(id, expected)| *id == Fr::from(expected)));
+    }
+    
+    // A one-request-per-connection HTTP node on a unix socket, as post_unix
+    // expects. `reply` turns each JSON-RPC request into the raw body to send
+    // back; the requests are recorded.
+    #[cfg(unix)]
+    fn socket_node(
+        name: &str,
+        reply: impl Fn(&Value) -> String + Send + 'static,
+    ) -> (PathBuf, Recorded) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        
+        let path = temp_path(&format!("{}.sock", name));
+        let _ = fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let record = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut raw = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let body = loop {
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    raw.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&raw).to_string();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else { continue };
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|n| n.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if body.len() >= length || n == 0 {
+                        break body.to_string();
+                    }
+                };
+                let request: Value = serde_json::from_str(&body).unwrap();
+                let answer = reply(&request);
+                record.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    answer.len(),
+                    answer
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (path, requests)
+    }
+    
+    #[cfg(unix)]
+    fn socket_client(path: &Path, retry: RetryPolicy) -> Result<ApiClient> {
+        let tls = TlsArgs { tls_ca_cert: None, danger_accept_invalid_certs: false };
+        ApiClient::new(format!("unix://{}", path.display()), Duration::from_secs(5), retry, Duration::from_secs(5), false, &tls, Duration::ZERO)
+    }
+    
+    #[cfg(unix)]
+    fn answer(request: &Value, result: Value) -> String {
+        json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string()
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_transport_reaches_the_node() {
+        let (path, requests) = socket_node("unix-transport", |request| answer(request, json!("2a")));
+        let client = socket_client(&path, fast_retry(0)).unwrap();
+        assert_eq!(client.get_balance("07").await.unwrap(), "2a");
+        
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["method"], "get_balance_by_owner");
+        assert_eq!(requests[0]["params"]["addr"], "07");
+        fs::remove_file(&path).unwrap();
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn missing_unix_socket_is_rejected_up_front() {
+        let err = socket_client(&temp_path("no-such.sock"), fast_retry(0)).err().unwrap();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn request_ids_advance_and_replies_must_match_them() {
+        let (path, requests) = socket_node("request-ids", |request| {
+            let id = request["id"].as_u64().unwrap();
+            // The third request gets an answer meant for another id.
+            let id = if id == 3 { id + 10 } else { id };
+            json!({ "jsonrpc": "2.0", "id": id, "result": "2a" }).to_string()
+        });
+        let client = socket_client(&path, fast_retry(0)).unwrap();
+        
+        assert_eq!(client.get_balance("07").await.unwrap(), "2a");
+        assert_eq!(client.get_balance("07").await.unwrap(), "2a");
+        let err = client.get_balance("07").await.unwrap_err().to_string();
+        assert!(err.contains("does not match request id 3"), "{}", err);
+        
+        let ids: Vec<u64> = requests.lock().unwrap().iter().map(|request| request["id"].as_u64().unwrap()).collect();
+        assert_eq!(ids, [1, 2, 3]);
+        fs::remove_file(&path).unwrap();
+    }
+    
+    fn utxo_with_data(len: usize) -> Out {
+        Out { amount: Fr::from(1u32), owner: Fr::from(7u32), data: vec![Fr::from(3u32); len] }
+    }
+    
+    #[test]
+    fn utxo_data_beyond_the_limit_is_rejected() {
+        assert_eq!(decode_utxo(&utxo_hex(&utxo_with_data(MAX_UTXO_DATA_LEN))).unwrap().data.len(), MAX_UTXO_DATA_LEN);
+        // 65 elements also overflow the byte bound, so either check may be
+        // the one that refuses it.
+        let err = decode_utxo(&utxo_hex(&utxo_with_data(MAX_UTXO_DATA_LEN + 1))).unwrap_err().to_string();
+        assert!(err.contains("more than the maximum of 64") || err.contains("too large"), "{}", err);
+    }
+    
+    #[test]
+    fn oversized_utxo_hex_is_rejected_before_decoding() {
+        // Not even hex: only the length check can have rejected it.
+        let err = decode_utxo(&"zz".repeat(MAX_UTXO_BYTES + 1)).unwrap_err().to_string();
+        assert!(err.contains("UTXO response too large"), "{}", err);
+    }
+    
+    #[test]
+    fn second_output_pays_two_owners_and_conserves_value() {
+        let own = Fr::from(7u32);
+        let (first, second) = (Fr::from(9u32), Fr::from(11u32));
+        let fee = Fr::from(3u32);
+        let selection = || Selection::from_inputs(numbered(&[10, 20], own)).unwrap();
+        
+        let plan = TransferPlan::new(selection(), first, Fr::from(12u32), Some((second, Fr::from(15u32))), fee, own, Fr::from(30u32)).unwrap();
+        assert!(plan.tx.ox.owner == first && plan.tx.ox.amount == Fr::from(12u32));
+        assert!(plan.tx.oy.owner == second && plan.tx.oy.amount == Fr::from(15u32));
+        assert!(plan.fee == fee && !plan.has_change);
+        assert!(check_value_conserved(&plan.inputs, &plan.tx, fee).is_ok());
+        
+        let err = TransferPlan::new(selection(), first, Fr::from(12u32), Some((second, Fr::from(10u32))), fee, own, Fr::from(30u32))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no room for change"), "{}", err);
+    }
+    
+    #[test]
+    fn fixed_seed_gives_a_fixed_secret_and_address() {
+        let seed: HexString = "11".repeat(32).parse().unwrap();
+        let first = seeded_secret(&seed).unwrap();
+        let again = create_secret(&mut StdRng::from_seed([0x11; 32]));
+        assert!(first.expose() == again.expose());
+        assert_eq!(generate_address(&first).unwrap(), generate_address(&again).unwrap());
+        
+        let other = seeded_secret(&"22".repeat(32).parse().unwrap()).unwrap();
+        assert!(other.expose() != first.expose());
+        assert!(seeded_secret(&"11".repeat(31).parse().unwrap()).is_err());
+    }
+    
+    fn transfer_cli(command: &str, from: Fr, to: Fr, amount: u32, extra: &[&str]) -> Cli {
+        let (from, to, amount) = (from.to_hex(), to.to_hex(), Fr::from(amount).to_hex());
+        let mut args = vec!["wallet", command, "--from", &from, "--to", &to, "--amount", &amount];
+        args.extend_from_slice(extra);
+        Cli::parse_from(args)
+    }
+    
+    fn transfer_args(cli: &Cli) -> &TransferArgs {
+        match &cli.command {
+            Commands::Transfer { args, .. } | Commands::TransferPermissionless { args } => args,
+            _ => unreachable!(),
+        }
+    }
+    
+    #[tokio::test]
+    async fn staged_coins_become_the_transfer_inputs() {
+        let from = Fr::from(7u32);
+        wallet_prover_ffi::stub::install(&from.to_hex());
+        let (api, _) = mock_client(utxo_node(vec![utxo(50, from), utxo(6, from), utxo(8, from)], WALK));
+        
+        let coins = fetch_owned_utxos(&api, &from.to_hex().parse().unwrap(), from, UtxoDetail::Full).await.unwrap();
+        let staged = [Fr::from(2u32), Fr::from(3u32)];
+        assert!(stage_record(&from.to_hex().parse().unwrap(), &coins, &[Fr::from(9u32)]).is_err());
+        let path = temp_path("staged.json");
+        fs::write(&path, serde_json::to_vec(&stage_record(&from.to_hex().parse().unwrap(), &coins, &staged).unwrap()).unwrap()).unwrap();
+        
+        let cli = transfer_cli("transfer-permissionless", from, Fr::from(9u32), 10, &["--inputs-file", path.to_str().unwrap(), "--dry-run"]);
+        let (tx, _) = run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[]).await.unwrap();
+        fs::remove_file(&path).unwrap();
+        // First fit would have taken the 50 coin alone.
+        assert!(tx.ix == staged[0] && tx.iy == staged[1]);
+        assert!(tx.oy.amount == Fr::from(1u32));
+    }
+    
+    // What a node that canonicalizes input order would hash: the two inputs
+    // in ascending order.
+    fn canonical_hash(tx: &Tx) -> Fr {
+        let mut canonical = tx.clone();
+        if Amount(canonical.iy) < Amount(canonical.ix) {
+            std::mem::swap(&mut canonical.ix, &mut canonical.iy);
+        }
+        canonical.hash()
+    }
+    
+    #[tokio::test]
+    async fn both_input_orders_build_valid_txs_with_one_canonical_hash() {
+        let from = Fr::from(7u32);
+        wallet_prover_ffi::stub::install(&from.to_hex());
+        let (api, _) = mock_client(utxo_node(vec![utxo(6, from), utxo(8, from)], WALK));
+        
+        let mut txs = Vec::new();
+        for order in ["natural", "swapped"] {
+            let cli = transfer_cli("transfer-permissionless", from, Fr::from(9u32), 10, &["--input-order", order, "--dry-run"]);
+            let (tx, report) = run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[]).await.unwrap();
+            assert_eq!(report["status"], "dry_run");
+            check_roundtrip(&hex::decode(report["wp_tx"].as_str().unwrap()).unwrap(), &tx).unwrap();
+            txs.push(tx);
+        }
+        
+        let (natural, swapped) = (&txs[0], &txs[1]);
+        assert!(natural.ix == Fr::from(1u32) && natural.iy == Fr::from(2u32));
+        assert!(swapped.ix == natural.iy && swapped.iy == natural.ix);
+        assert!(swapped.ox.amount == natural.ox.amount && swapped.oy.amount == natural.oy.amount);
+        assert!(canonical_hash(natural) == canonical_hash(swapped));
+    }
+    
+    #[test]
+    fn roundtrip_check_rejects_bytes_of_a_different_tx() {
+        let from = Fr::from(7u32);
+        wallet_prover_ffi::stub::install(&from.to_hex());
+        let tx = construct_tx(Fr::from(1u32), Fr::from(2u32), (Fr::from(9u32), Fr::from(5u32)), (from, Fr::from(4u32)));
+        let bytes = prove_tx(&tx, Authority::Permissionless, from, false, false, None).unwrap();
+        assert!(check_roundtrip(&bytes, &tx).is_ok());
+        
+        // As if enc and dec disagreed: the bytes decode, but to another tx.
+        let mut drifted = tx.clone();
+        drifted.oy.amount = Fr::from(3u32);
+        let err = check_roundtrip(&bytes, &drifted).unwrap_err().to_string();
+        assert!(err.contains("enc/dec asymmetry"), "{}", err);
+        
+        let mut other_input = tx.clone();
+        other_input.iy = Fr::from(5u32);
+        assert!(check_roundtrip(&bytes, &other_input).is_err());
+    }
+    
+    #[test]
+    fn receive_payload_round_trips_through_the_uri_parser() {
+        let account: HexString = Fr::from(0xabcdu32).to_hex().parse().unwrap();
+        let amount: HexString = Fr::from(42u32).to_hex().parse().unwrap();
+        let memo = "rent & bills = 100% / März?".to_string();
+        
+        let (request, uri) = payment_request(&account, Some(&amount), Some(&memo)).unwrap();
+        let parsed = PaymentRequest::parse(&uri).unwrap();
+        assert!(parsed.account == request.account);
+        assert!(parsed.amount == Some(Fr::from(42u32)));
+        assert_eq!(parsed.memo.as_deref(), Some(memo.as_str()));
+        
+        let (bare, uri) = payment_request(&account, None, None).unwrap();
+        assert_eq!(uri, format!("{}{}", uri::SCHEME, bare.account.to_hex()));
+        let parsed = PaymentRequest::parse(&uri).unwrap();
+        assert!(parsed.account == bare.account && parsed.amount.is_none() && parsed.memo.is_none());
+    }
+    
+    #[test]
+    fn amount_that_wrapped_below_zero_is_flagged() {
+        let wrapped = format_amount(Fr::from(0u32) - Fr::from(1u32));
+        assert!(wrapped.ends_with("[likely underflow/invalid]"), "{}", wrapped);
+        
+        let plain = format_amount(Fr::from(1000u32));
+        assert_eq!(plain, format!("amount=1000 (0x{})", Fr::from(1000u32).to_hex()));
+    }
+    
+    // Answers each id after a delay that shrinks as the id grows, so later
+    // fetches finish first, and records how many calls were in flight.
+    struct SlowTransport {
+        in_flight: AtomicUsize,
+        peak: Arc<AtomicUsize>,
+    }
+    
+    #[async_trait]
+    impl RpcTransport for SlowTransport {
+        async fn call(&self, _method: &str, params: Value) -> Result<Value> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            let id = params["id"].as_str().unwrap().to_string();
+            let rank = u64::from(*hex::decode(&id).unwrap().last().unwrap());
+            tokio::time::sleep(Duration::from_millis(5 * (10 - rank))).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(json!(id))
+        }
+        
+        async fn call_with_timeout(&self, method: &str, params: Value, _timeout: Duration) -> Result<Value> {
+            self.call(method, params).await
+        }
+        
+        async fn call_batch(&self, _calls: &[(&str, Value)]) -> Result<Vec<Result<Value>>> {
+            Err(method_not_found())
+        }
+    }
+    
+    #[tokio::test]
+    async fn per_utxo_fetches_overlap_and_keep_id_order() {
+        let peak = Arc::new(AtomicUsize::new(0));
+        let transport = SlowTransport { in_flight: AtomicUsize::new(0), peak: peak.clone() };
+        let api = ApiClient::with_transport(Box::new(transport), fast_retry(0), Duration::from_secs(1), Duration::ZERO);
+        let ids: Vec<Fr> = (1..=8u32).map(Fr::from).collect();
+        
+        let results = api.call_per_utxo("get_utxo_amount", &ids, 4).await;
+        let answered: Vec<String> = results.into_iter().map(|result| result.unwrap().as_str().unwrap().to_string()).collect();
+        let expected: Vec<String> = ids.iter().map(|id| id.to_hex()).collect();
+        assert_eq!(answered, expected);
+        assert_eq!(peak.load(Ordering::SeqCst), 4);
+    }
+    
+    #[tokio::test]
+    async fn paying_the_derived_address_needs_allow_self() {
+        let (from, derived) = (Fr::from(7u32), Fr::from(11u32));
+        wallet_prover_ffi::stub::install(&derived.to_hex());
+        let (api, _) = mock_client(utxo_node(vec![utxo(50, from)], WALK));
+        let secret_hex = "33".repeat(32);
+        let secret = Secret::from_hex(&secret_hex.parse().unwrap()).unwrap();
+        
+        let cli = transfer_cli("transfer", from, derived, 10, &["--secret", &secret_hex, "--dry-run"]);
+        let err = run_transfer(&api, &cli, transfer_args(&cli), Authority::Secret(&secret), &[]).await.unwrap_err();
+        assert!(err.to_string().contains("self-payment"), "{}", err);
+        
+        let cli = transfer_cli("transfer", from, derived, 10, &["--secret", &secret_hex, "--dry-run", "--allow-self"]);
+        let (tx, _) = run_transfer(&api, &cli, transfer_args(&cli), Authority::Secret(&secret), &[]).await.unwrap();
+        assert!(tx.ox.owner == derived);
+    }
+    
+    #[test]
+    fn greedy_selection_takes_the_largest_coins_until_covered() {
+        let owner = Fr::from(7u32);
+        let coins = numbered(&[3, 9, 1, 5, 4], owner);
+        let ids = |selected: Vec<(Fr, Out)>| selected.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        
+        let picked = select_utxos_greedy(coins.clone(), Amount(Fr::from(12u32))).unwrap();
+        assert!(ids(picked) == vec![Fr::from(2u32), Fr::from(4u32)]);
+        
+        // 17 needs the 9, 5 and 4: exactly three inputs.
+        let picked = select_utxos_greedy(coins.clone(), Amount(Fr::from(17u32))).unwrap();
+        assert_eq!(picked.len(), 3);
+        assert!(ids(picked) == vec![Fr::from(2u32), Fr::from(4u32), Fr::from(5u32)]);
+        
+        assert_eq!(select_utxos_greedy(coins.clone(), Amount(Fr::from(22u32))).unwrap().len(), 5);
+        assert!(select_utxos_greedy(coins, Amount(Fr::from(23u32))).is_none());
+        assert!(select_utxos_greedy(Vec::new(), Amount(Fr::from(1u32))).is_none());
+    }
+    
+    fn configured_cli(config: &config::Config, args: &[&str]) -> Cli {
+        let matches = Cli::command().try_get_matches_from(args).unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        cli.apply_config(config, &matches).unwrap();
+        cli
+    }
+    
+    #[test]
+    fn config_fills_absent_flags_and_loses_to_given_ones() {
+        let config: config::Config = toml::from_str(
+            "api_url = \"http://config:1\"\ntimeout_secs = 7\nconcurrency = 3\nfee = \"05\"",
+        )
+        .unwrap();
+        let (from, to) = (Fr::from(7u32).to_hex(), Fr::from(9u32).to_hex());
+        let transfer = ["wallet", "transfer-permissionless", "--from", &from, "--to", &to, "--amount", "0a"];
+        
+        let cli = configured_cli(&config, &transfer);
+        assert_eq!(cli.api_url, "http://config:1");
+        assert_eq!(cli.timeout_secs, 7);
+        assert_eq!(transfer_args(&cli).concurrency, 3);
+        assert_eq!(transfer_args(&cli).fee.as_ref().map(|fee| fee.to_string()), Some("05".to_string()));
+        
+        let mut flagged = vec!["wallet", "--api-url", "http://flag:2", "--timeout-secs", "9"];
+        flagged.extend_from_slice(&transfer[1..]);
+        flagged.extend_from_slice(&["--concurrency", "5", "--fee", "06"]);
+        let cli = configured_cli(&config, &flagged);
+        assert_eq!(cli.api_url, "http://flag:2");
+        assert_eq!(cli.timeout_secs, 9);
+        assert_eq!(transfer_args(&cli).concurrency, 5);
+        assert_eq!(transfer_args(&cli).fee.as_ref().map(|fee| fee.to_string()), Some("06".to_string()));
+    }
+    
+    #[test]
+    fn config_file_beats_the_environment() {
+        std::env::set_var("API_HTTP_URL", "http://env:3");
+        let config = config::Config { api_url: Some("http://config:1".to_string()), ..Default::default() };
+        let cli = configured_cli(&config, &["wallet", "list-methods"]);
+        let unconfigured = configured_cli(&config::Config::default(), &["wallet", "list-methods"]);
+        std::env::remove_var("API_HTTP_URL");
+        assert_eq!(cli.api_url, "http://config:1");
+        assert_eq!(unconfigured.api_url, "http://env:3");
+    }
+    
+    // Collects the log lines of the calling thread at or above `level`.
+    struct CaptureLogger;
+    
+    thread_local! {
+        static CAPTURED: RefCell<Option<(log::LevelFilter, Vec<String>)>> = const { RefCell::new(None) };
+    }
+    
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+        
+        fn log(&self, record: &log::Record) {
+            CAPTURED.with_borrow_mut(|captured| {
+                if let Some((level, lines)) = captured {
+                    if record.level() <= *level {
+                        lines.push(record.args().to_string());
+                    }
+                }
+            });
+        }
+        
+        fn flush(&self) {}
+    }
+    
+    async fn capture_logs<T>(level: log::LevelFilter, body: impl std::future::Future<Output = T>) -> (T, Vec<String>) {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        CAPTURED.set(Some((level, Vec::new())));
+        let result = body.await;
+        let (_, lines) = CAPTURED.take().unwrap();
+        (result, lines)
+    }
+    
+    #[tokio::test]
+    async fn info_logging_leaves_out_per_utxo_lines() {
+        let from = Fr::from(7u32);
+        wallet_prover_ffi::stub::install(&from.to_hex());
+        let (api, _) = mock_client(utxo_node(vec![utxo(50, from), utxo(6, from), utxo(8, from)], WALK));
+        let cli = transfer_cli("transfer-permissionless", from, Fr::from(9u32), 10, &["--dry-run"]);
+        let per_utxo = |lines: &[String]| lines.iter().filter(|line| line.starts_with("UTXO: id=")).count();
+        
+        let (result, info) = capture_logs(log::LevelFilter::Info, run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[])).await;
+        result.unwrap();
+        assert!(info.iter().any(|line| line == "Fetched 3 UTXOs"), "{:?}", info);
+        assert_eq!(per_utxo(&info), 0, "{:?}", info);
+        
+        let (result, debug) = capture_logs(log::LevelFilter::Debug, run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[])).await;
+        result.unwrap();
+        assert_eq!(per_utxo(&debug), 3, "{:?}", debug);
+    }
+    
+    #[test]
+    fn utxo_summary_totals_and_bounds_a_crafted_set() {
+        let owner = Fr::from(7u32);
+        let summary = summarize_utxos(&[utxo(40, owner), utxo(3, owner), utxo(250, owner), utxo(3, owner)]).unwrap();
+        assert!(summary.total == Amount(Fr::from(296u32)));
+        assert!(summary.largest == Some(Amount(Fr::from(250u32))));
+        assert!(summary.smallest == Some(Amount(Fr::from(3u32))));
+        
+        let empty = summarize_utxos(&[]).unwrap();
+        assert!(empty.total == Amount::zero() && empty.largest.is_none() && empty.smallest.is_none());
+        
+        let near_modulus = Fr::from(0u32) - Fr::from(1u32);
+        let huge = Out { amount: near_modulus, owner, data: Vec::new() };
+        assert!(summarize_utxos(&[huge.clone(), huge]).is_err());
+    }
+    
+    #[tokio::test]
+    async fn malformed_to_is_refused_by_parse_address_before_any_call() {
+        let from = Fr::from(7u32);
+        let ok = Fr::from(9u32).to_hex();
+        let full_width = "ff".repeat(32);
+        let cases = [
+            ("ab".repeat(31), "expected 32 bytes, got 31"),
+            ("ab".repeat(33), "expected 32 bytes, got 33"),
+            (BLS12_381_MODULUS.to_string(), "not a canonical field element"),
+            (full_width, "not a canonical field element"),
+        ];
+        for (to, reason) in cases {
+            let err = parse_address(&to.parse().unwrap()).unwrap_err().to_string();
+            assert!(err.contains(reason), "{}: {}", to, err);
+            
+            let (api, calls) = mock_client(utxo_node(vec![utxo(50, from)], WALK));
+            let (from_hex, amount) = (from.to_hex(), Fr::from(10u32).to_hex());
+            let cli = Cli::parse_from(["wallet", "transfer-permissionless", "--from", &from_hex, "--to", &to, "--amount", &amount, "--dry-run"]);
+            let err = run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[]).await.unwrap_err().to_string();
+            assert!(err.contains(reason), "{}: {}", to, err);
+            assert!(calls.lock().unwrap().is_empty(), "{}", to);
+        }
+        assert!(parse_address(&ok.parse().unwrap()).unwrap() == Fr::from(9u32));
+    }
+    
+    // Never answers; like the HTTP transport it gives up once the
+    // per-request timeout passes.
+    struct HangingTransport;
+    
+    #[async_trait]
+    impl RpcTransport for HangingTransport {
+        async fn call(&self, method: &str, params: Value) -> Result<Value> {
+            self.call_with_timeout(method, params, Duration::from_secs(30)).await
+        }
+        
+        async fn call_with_timeout(&self, _method: &str, _params: Value, timeout: Duration) -> Result<Value> {
+            Ok(tokio::time::timeout(timeout, std::future::pending()).await?)
+        }
+    }
+    
+    #[tokio::test]
+    async fn hanging_submission_reports_submit_timeout() {
+        let api = ApiClient::with_transport(Box::new(HangingTransport), fast_retry(0), Duration::from_secs(1), Duration::ZERO);
+        let started = Instant::now();
+        let err = api.submit_transaction("00", "key").await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let timeout = err.downcast_ref::<SubmitTimeout>().expect("a SubmitTimeout");
+        assert_eq!(timeout.secs, 1);
+        assert!(err.to_string().starts_with("submission timed out after 1s"), "{}", err);
+    }
+    
+    #[tokio::test]
+    async fn foreign_owned_utxo_is_excluded() {
+        let (own, foreign) = (Fr::from(7u32), Fr::from(8u32));
+        assert!(is_owned_by(&utxo(5, own), own));
+        
+        let (excluded, warnings) = capture_logs(log::LevelFilter::Warn, async { !is_owned_by(&utxo(5, foreign), own) }).await;
+        assert!(excluded);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains(&foreign.to_hex()) && warnings[0].contains(&own.to_hex()), "{}", warnings[0]);
+        
+        let (api, _) = mock_client(utxo_node(vec![utxo(5, own), utxo(6, foreign), utxo(7, own)], WALK));
+        let owned = fetch_owned_utxos(&api, &own.to_hex().parse().unwrap(), own, UtxoDetail::Full).await.unwrap();
+        assert!(owned.iter().map(|(id, _)| *id).collect::<Vec<_>>() == vec![Fr::from(1u32), Fr::from(3u32)]);
+    }
+    
+    #[test]
+    fn utxo_with_trailing_bytes_is_not_canonical() {
+        let canonical = utxo_hex(&utxo(5, Fr::from(7u32)));
+        assert!(decode_utxo(&canonical).unwrap().amount == Fr::from(5u32));
+        
+        let err = decode_utxo(&format!("{}00ff", canonical)).unwrap_err().to_string();
+        assert!(err.contains("re-encodes to 65 bytes instead of 67"), "{}", err);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn no_network_fails_rpc_commands_before_reaching_the_node() {
+        let (path, requests) = socket_node("no-network", |request| answer(request, json!("")));
+        let api_url = format!("unix://{}", path.display());
+        let account = Fr::from(7u32).to_hex();
+        
+        for command in [vec!["get-balance", "--account", &account], vec!["list-utxos", "--account", &account], vec!["node-info"]] {
+            let mut args = vec!["wallet", "--no-network", "--api-url", &api_url];
+            args.extend(command);
+            let err = run(&Cli::parse_from(&args)).await.unwrap_err().to_string();
+            assert!(err.contains("network access is disabled (--no-network)"), "{:?}: {}", args, err);
+        }
+        assert!(requests.lock().unwrap().is_empty());
+        
+        let reached = run(&Cli::parse_from(["wallet", "--api-url", &api_url, "get-balance", "--account", &account])).await;
+        assert!(reached.is_ok(), "{:?}", reached.err());
+        assert!(!requests.lock().unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn idempotency_key_rides_every_submit_attempt_unchanged() {
+        let from = Fr::from(7u32);
+        wallet_prover_ffi::stub::install(&from.to_hex());
+        for (extra, chosen) in [(&[][..], None), (&["--idempotency-key", "order-17"][..], Some("order-17"))] {
+            let node = utxo_node(vec![utxo(50, from)], WALK);
+            let (lossy, submits) = lossy_submit_node(false);
+            let handler = move |method: &str, params: &Value| match method {
+                "submit_transaction" => lossy(method, params),
+                _ => node(method, params),
+            };
+            let (api, _) = mock_client_with(handler, fast_retry(3));
+            let mut args = vec!["--yes"];
+            args.extend_from_slice(extra);
+            let cli = transfer_cli("transfer-permissionless", from, Fr::from(9u32), 10, &args);
+            
+            let (tx, _) = run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[]).await.unwrap();
+            let submits = submits.lock().unwrap();
+            assert_eq!(submits.len(), 2);
+            let expected = chosen.map_or_else(|| tx.hash().to_hex(), str::to_string);
+            for submit in submits.iter() {
+                assert_eq!(submit["idempotency_key"], expected.as_str());
+            }
+            assert_eq!(submits[0]["tx"], submits[1]["tx"]);
+        }
+    }
+    
+    #[tokio::test]
+    async fn list_methods_prefers_rpc_discover_and_falls_back_to_list_methods() {
+        let (api, calls) = mock_client(|method, _| match method {
+            "rpc.discover" => Ok(json!({ "methods": [{ "name": "get_utxo" }, { "name": "submit_transaction" }] })),
+            _ => Err(anyhow!("unexpected call to {}", method)),
+        });
+        assert_eq!(api.list_methods().await.unwrap(), ["get_utxo", "submit_transaction"]);
+        assert_eq!(*calls.lock().unwrap(), ["rpc.discover"]);
+        
+        let (api, calls) = mock_client(|method, _| match method {
+            "list_methods" => Ok(json!(["get_balance_by_owner"])),
+            _ => Err(method_not_found()),
+        });
+        assert_eq!(api.list_methods().await.unwrap(), ["get_balance_by_owner"]);
+        assert_eq!(*calls.lock().unwrap(), ["rpc.discover", "list_methods"]);
+        
+        let (api, _) = mock_client(|method, _| match method {
+            "list_methods" => Ok(json!({ "not": "a list" })),
+            _ => Err(method_not_found()),
+        });
+        assert_eq!(api.list_methods().await.unwrap_err().to_string(), "Invalid list_methods format");
+    }
+    
+    #[tokio::test]
+    async fn malformed_prover_address_is_reported_as_a_failed_proof() {
+        for address in ["", "zz", "abcd", &"ab".repeat(33)] {
+            let err = decode_prover_address(address).unwrap_err().to_string();
+            assert_eq!(err, format!("prover returned an invalid address {:?}; proof likely failed", address));
+        }
+        
+        let from = Fr::from(7u32);
+        wallet_prover_ffi::stub::install("abcd");
+        let (api, _) = mock_client(utxo_node(vec![utxo(50, from)], WALK));
+        let secret_hex = "33".repeat(32);
+        let secret = Secret::from_hex(&secret_hex.parse().unwrap()).unwrap();
+        let cli = transfer_cli("transfer", from, Fr::from(9u32), 10, &["--secret", &secret_hex, "--dry-run"]);
+        let err = run_transfer(&api, &cli, transfer_args(&cli), Authority::Secret(&secret), &[]).await.unwrap_err();
+        assert!(err.to_string().contains("prover returned an invalid address \"abcd\""), "{}", err);
+    }
+    
+    #[tokio::test]
+    async fn simulate_validates_and_never_submits() {
+        let from = Fr::from(7u32);
+        wallet_prover_ffi::stub::install(&from.to_hex());
+        for (verdict, status) in [(json!(true), "accepted"), (json!({ "valid": false, "error": "double spend" }), "rejected")] {
+            let node = utxo_node(vec![utxo(50, from)], WALK);
+            let validated = Arc::new(Mutex::new(Vec::new()));
+            let record = validated.clone();
+            let handler = move |method: &str, params: &Value| match method {
+                "validate_transaction" => {
+                    record.lock().unwrap().push(params["tx"].clone());
+                    Ok(verdict.clone())
+                }
+                _ => node(method, params),
+            };
+            let (api, calls) = mock_client(handler);
+            let cli = transfer_cli("transfer-permissionless", from, Fr::from(9u32), 10, &["--simulate", "--yes"]);
+            
+            let (_, report) = run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[]).await.unwrap();
+            assert_eq!(report["status"], status);
+            assert_eq!(validated.lock().unwrap().len(), 1);
+            assert!(!calls.lock().unwrap().iter().any(|method| method == "submit_transaction"));
+        }
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn simulate_tx_command_only_validates() {
+        let (path, requests) = socket_node("simulate-tx", |request| answer(request, json!(true)));
+        let api_url = format!("unix://{}", path.display());
+        run(&Cli::parse_from(["wallet", "--api-url", &api_url, "simulate-tx", "0xabcd"])).await.unwrap();
+        
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["method"], "validate_transaction");
+        assert_eq!(requests[0]["params"]["tx"], "abcd");
+        fs::remove_file(&path).unwrap();
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn non_json_body_is_retried_then_surfaced_with_a_snippet() {
+        let page = "<html><body>502 Bad Gateway</body></html>";
+        let flaky = AtomicUsize::new(0);
+        let (path, requests) = socket_node("non-json-flaky", move |request| {
+            if flaky.fetch_add(1, Ordering::SeqCst) < INVALID_JSON_RETRIES { page.to_string() } else { answer(request, json!("")) }
+        });
+        let client = socket_client(&path, fast_retry(0)).unwrap();
+        assert_eq!(client.get_balance("00").await.unwrap(), "");
+        assert_eq!(requests.lock().unwrap().len(), INVALID_JSON_RETRIES + 1);
+        fs::remove_file(&path).unwrap();
+        
+        let (path, requests) = socket_node("non-json", move |_| page.to_string());
+        let client = socket_client(&path, fast_retry(0)).unwrap();
+        let err = client.get_balance("00").await.unwrap_err();
+        let invalid = err.downcast_ref::<InvalidJsonResponse>().expect("an InvalidJsonResponse");
+        assert_eq!(invalid.method, "get_balance_by_owner");
+        assert_eq!(invalid.snippet, page);
+        assert!(err.to_string().contains("body starts with \"<html>"), "{}", err);
+        assert_eq!(requests.lock().unwrap().len(), INVALID_JSON_RETRIES + 1);
+        fs::remove_file(&path).unwrap();
+    }
+    
+    #[test]
+    fn out_renders_the_same_in_text_and_json() {
+        let owner = Fr::from(7u32);
+        let out = Out { amount: Fr::from(1234u32), owner, data: vec![Fr::from(1u32), Fr::from(2u32)] };
+        let amount = format!("amount=1234 (0x{})", Fr::from(1234u32).to_hex());
+        
+        assert_eq!(format_out(&out, OutFormat::default()), amount);
+        assert_eq!(format_out(&out, OutFormat { owner: true, data: false }), format!("{}, owner={}", amount, owner.to_hex()));
+        assert_eq!(
+            format_out(&out, OutFormat { owner: true, data: true }),
+            format!("{}, owner={}, data=[{}, {}]", amount, owner.to_hex(), Fr::from(1u32).to_hex(), Fr::from(2u32).to_hex())
+        );
+        
+        let rendered = out_to_json(&out);
+        assert_eq!(rendered["amount"], amount_json(out.amount));
+        assert_eq!(rendered["amount"]["decimal"], "1234");
+        assert_eq!(rendered["owner"], owner.to_hex());
+        assert_eq!(rendered["data"], json!([Fr::from(1u32).to_hex(), Fr::from(2u32).to_hex()]));
+        assert_eq!(rendered["likely_underflow"], false);
+        
+        let wrapped = Out { amount: Fr::from(0u32) - Fr::from(1u32), owner, data: Vec::new() };
+        assert!(format_out(&wrapped, OutFormat { owner: true, data: true }).contains("[likely underflow/invalid], owner="));
+        assert_eq!(out_to_json(&wrapped)["likely_underflow"], true);
+    }
+    
+    #[tokio::test]
+    async fn zero_account_and_zero_secret_are_refused() {
+        let zero = Fr::from(0u32);
+        assert!(reject_zero("from", Fr::from(1u32)).is_ok());
+        assert_eq!(
+            reject_zero("from", zero).unwrap_err().to_string(),
+            "Refusing to operate on the zero account: --from is all zeros"
+        );
+        assert!(reject_zero_secret(&Secret::new(Fr::from(1u32))).is_ok());
+        assert!(reject_zero_secret(&Secret::new(zero)).unwrap_err().to_string().contains("--secret is all zeros"));
+        
+        let (api, calls) = mock_client(utxo_node(vec![utxo(50, zero)], WALK));
+        let cli = transfer_cli("transfer-permissionless", zero, Fr::from(9u32), 10, &["--dry-run"]);
+        let err = run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[]).await.unwrap_err();
+        assert!(err.to_string().contains("--from is all zeros"), "{}", err);
+        assert!(calls.lock().unwrap().is_empty());
+        
+        // Refused before the client ever talks to the (absent) node.
+        let zero_secret = "00".repeat(32);
+        let cli = transfer_cli("transfer", Fr::from(7u32), Fr::from(9u32), 10, &["--secret", &zero_secret, "--dry-run"]);
+        let err = run(&cli).await.unwrap_err();
+        assert!(err.to_string().contains("--secret is all zeros"), "{}", err);
+    }
+    
+    #[tokio::test]
+    async fn per_utxo_failures_are_typed_and_keep_their_id() {
+        let good = utxo_hex(&utxo(5, Fr::from(7u32)));
+        let (api, _) = mock_client(move |method, params| match (method, params["id"].as_str()) {
+            ("batch", _) => Err(method_not_found()),
+            ("get_utxo", Some(id)) if id == Fr::from(1u32).to_hex() => Ok(json!(good)),
+            ("get_utxo", Some(id)) if id == Fr::from(2u32).to_hex() => {
+                Err(RpcError(json!({ "code": -32000, "message": "pruned" })).into())
+            }
+            ("get_utxo", _) => Ok(json!("zz")),
+            _ => Err(anyhow!("unexpected call to {}", method)),
+        });
+        let ids = [Fr::from(1u32), Fr::from(2u32), Fr::from(3u32)];
+        let results = fetch_and_decode_utxos(&api, &ids).await;
+        
+        assert!(matches!(&results[0], Ok((id, utxo)) if *id == ids[0] && utxo.amount == Fr::from(5u32)));
+        assert!(matches!(&results[1], Err(UtxoError::Fetch { id, error }) if *id == ids[1] && error.is::<RpcError>()));
+        assert!(matches!(&results[2], Err(UtxoError::Decode { id, .. }) if *id == ids[2]));
+        let message = results[2].as_ref().unwrap_err().to_string();
+        assert!(message.starts_with(&format!("failed to decode UTXO {}", ids[2].to_hex())), "{}", message);
+    }
+    
+    #[test]
+    fn gzipped_archive_reads_back_like_a_plain_one() {
+        let tx = construct_tx(Fr::from(1u32), Fr::from(2u32), (Fr::from(7u32), Fr::from(5u32)), (Fr::from(7u32), Fr::from(0u32)));
+        let submitted = [9u8, 8, 7, 6];
+        let dir = std::env::temp_dir().join(format!("wallet-archive-gzip-test-{}", std::process::id()));
+        let gzipped = archive_submission(&dir, &tx, &submitted, true).unwrap();
+        let plain = archive_submission(&dir, &tx, &submitted, false).unwrap();
+        
+        assert_eq!(gzipped.file_name().unwrap().to_str().unwrap(), format!("{}.json.gz", tx.hash().to_hex()));
+        assert!(fs::read(&gzipped).unwrap().starts_with(&[0x1f, 0x8b]));
+        let (mut unzipped, mut record) = (read_archive(&gzipped).unwrap(), read_archive(&plain).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        
+        assert_eq!(unzipped["wp_tx"], hex::encode(submitted));
+        assert!(codec::check_version(&unzipped).is_ok());
+        unzipped["timestamp"].take();
+        record["timestamp"].take();
+        assert_eq!(unzipped, record);
+    }
+    
+    #[tokio::test]
+    async fn repeat_runs_each_round_on_fresh_inputs_and_counts_failures() {
+        let from = Fr::from(7u32);
+        wallet_prover_ffi::stub::install(&from.to_hex());
+        let node = utxo_node(vec![utxo(20, from), utxo(20, from)], WALK);
+        let submits = Arc::new(Mutex::new(Vec::new()));
+        let record = submits.clone();
+        let (api, _) = mock_client(move |method, params| match method {
+            "submit_transaction" => {
+                record.lock().unwrap().push(decode_tx_inputs(params["tx"].as_str().unwrap()));
+                Ok(Value::Null)
+            }
+            _ => node(method, params),
+        });
+        let cli = transfer_cli("transfer-permissionless", from, Fr::from(9u32), 10, &["--repeat", "3", "--yes"]);
+        
+        let err = run_transfers(&api, &cli, transfer_args(&cli), Authority::Permissionless).await.unwrap_err();
+        assert_eq!(err.to_string(), "1 of 3 transfers failed");
+        let submits = submits.lock().unwrap();
+        assert_eq!(submits.len(), 2);
+        assert!(submits[0].0 != submits[1].0, "both rounds spent {}", submits[0].0.to_hex());
+    }
+    
+    // The (ix, iy) of a submitted Wp tx hex.
+    fn decode_tx_inputs(wp_tx_hex: &str) -> (Fr, Fr) {
+        let bytes = hex::decode(wp_tx_hex).unwrap();
+        let Wp { val: tx, .. } = Wp::<Tx>::dec(&mut bytes.into_iter()).unwrap();
+        (tx.ix, tx.iy)
+    }
+    
+    #[tokio::test]
+    async fn balance_sources_that_disagree_are_warned_about() {
+        let own = Fr::from(7u32);
+        let account: HexString = own.to_hex().parse().unwrap();
+        let disagreement = |lines: &[String]| lines.iter().filter(|line| line.contains("disagrees with summed UTXOs")).count();
+        for (node_balance, warnings) in [(99u32, 1), (56, 0)] {
+            let node = utxo_node(vec![utxo(50, own), utxo(6, own)], WALK);
+            let (api, _) = mock_client(move |method, params| match method {
+                "get_balance_by_owner" => Ok(json!(Fr::from(node_balance).to_hex())),
+                _ => node(method, params),
+            });
+            
+            let (balance, lines) = capture_logs(log::LevelFilter::Warn, cross_checked_balance(&api, &account, BalanceSource::Node)).await;
+            assert!(balance.unwrap() == Fr::from(node_balance));
+            assert_eq!(disagreement(&lines), warnings, "{:?}", lines);
+            
+            let (balance, lines) = capture_logs(log::LevelFilter::Warn, cross_checked_balance(&api, &account, BalanceSource::Utxos)).await;
+            assert!(balance.unwrap() == Fr::from(56u32));
+            assert_eq!(disagreement(&lines), warnings, "{:?}", lines);
+        }
+    }
+    
+    #[test]
+    fn bad_tls_ca_path_is_rejected_when_the_client_is_built() {
+        let client = |path: &Path| {
+            let tls = TlsArgs { tls_ca_cert: Some(path.to_path_buf()), danger_accept_invalid_certs: false };
+            ApiClient::new("https://node:8443".to_string(), Duration::from_secs(1), fast_retry(0), Duration::from_secs(1), false, &tls, Duration::ZERO)
+        };
+        
+        let missing = temp_path("missing-ca.pem");
+        let err = client(&missing).err().expect("a missing CA file is an error").to_string();
+        assert!(err.starts_with(&format!("Failed to read TLS CA certificate {}", missing.display())), "{}", err);
+        
+        let garbage = temp_path("garbage-ca.pem");
+        fs::write(&garbage, "-----BEGIN CERTIFICATE-----\nnot base64!\n-----END CERTIFICATE-----\n").unwrap();
+        let result = client(&garbage);
+        fs::remove_file(&garbage).unwrap();
+        assert!(result.is_err(), "a malformed CA file is an error");
+    }
+    
+    #[tokio::test]
+    async fn transfer_plan_is_printed_before_proving() {
+        let from = Fr::from(7u32);
+        // An address the proof output can't carry, so proving itself fails.
+        wallet_prover_ffi::stub::install("abcd");
+        let (api, _) = mock_client(utxo_node(vec![utxo(50, from)], WALK));
+        let cli = transfer_cli("transfer-permissionless", from, Fr::from(9u32), 10, &["--dry-run"]);
+        output::take_transcript();
+        
+        let err = run_transfer(&api, &cli, transfer_args(&cli), Authority::Permissionless, &[]).await.unwrap_err();
+        assert!(err.to_string().contains("address"), "{}", err);
+        assert!(wallet_prover_ffi::stub::calls() == ["GenerateProofPermissionless"]);
+        let transcript = output::take_transcript();
+        let plan = transcript.iter().position(|line| line == "Transfer plan:").expect("the plan was printed");
+        assert!(transcript[plan..].iter().any(|line| line.starts_with("  Fee: ")), "{:?}", transcript);
+    }
+    
+    #[tokio::test]
+    async fn tail_is_served_from_cache_within_its_ttl() {
+        let tails = AtomicUsize::new(0);
+        let handler = move |method: &str, _: &Value| match method {
+            "get_tail" => Ok(json!(format!("tail-{}", tails.fetch_add(1, Ordering::SeqCst)))),
+            _ => Err(anyhow!("unexpected call to {}", method)),
+        };
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let transport = MockTransport { handler: Box::new(handler), calls: calls.clone() };
+        let api = ApiClient::with_transport(Box::new(transport), fast_retry(0), Duration::from_secs(1), Duration::from_millis(100));
+        
+        assert_eq!(api.get_tail().await.unwrap(), "tail-0");
+        assert_eq!(api.get_tail().await.unwrap(), "tail-0");
+        assert_eq!(calls.lock().unwrap().len(), 1);
+        
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(api.get_tail().await.unwrap(), "tail-1");
+        assert_eq!(calls.lock().unwrap().len(), 2);
+        
+        // A zero TTL caches nothing.
+        let (uncached, calls) = mock_client(|_, _| Ok(json!("tail")));
+        uncached.get_tail().await.unwrap();
+        uncached.get_tail().await.unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 2);
+    }
+    
+    #[tokio::test]
+    async fn consolidate_refuses_an_account_the_secret_does_not_own() {
+        let (own, other) = (Fr::from(7u32), Fr::from(8u32));
+        wallet_prover_ffi::stub::install(&own.to_hex());
+        let secret = Secret::new(Fr::from(33u32));
+        let cli = Cli::parse_from(["wallet", "node-info"]);
+        let (other_hex, own_hex): (HexString, HexString) = (other.to_hex().parse().unwrap(), own.to_hex().parse().unwrap());
+        let args = |account| ConsolidateArgs {
+            account,
+            fee: Fr::from(3u32),
+            target: None,
+            dry_run: true,
+            yes: false,
+            wait_timeout: Duration::from_secs(1),
+        };
+        
+        let (api, calls) = mock_client(utxo_node(vec![utxo(5, other), utxo(6, other)], WALK));
+        let err = run_consolidate(&api, &cli, args(&other_hex), &secret).await.unwrap_err().to_string();
+        assert_eq!(err, format!("This secret belongs to {}, but --account is {}", own.to_hex(), other.to_hex()));
+        assert!(calls.lock().unwrap().is_empty());
+        
+        let (api, _) = mock_client(utxo_node(vec![utxo(5, own), utxo(6, own)], WALK));
+        run_consolidate(&api, &cli, args(&own_hex), &secret).await.unwrap();
+    }
 }
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::Value;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static EMITTED: AtomicBool = AtomicBool::new(false);
+
+// Under test, everything said on a thread is also kept for it to inspect.
+#[cfg(test)]
+thread_local! {
+    static TRANSCRIPT: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+macro_rules! say {
+    ($($arg:tt)*) => {
+        $crate::output::say(format_args!($($arg)*))
+    };
+}
+
+pub fn set_json(enabled: bool) {
+    JSON.store(enabled, Ordering::Relaxed);
+}
+
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+// In JSON mode stdout carries only the final object, so prose goes to stderr.
+pub fn say(args: fmt::Arguments) {
+    #[cfg(test)]
+    TRANSCRIPT.with_borrow_mut(|transcript| transcript.push(args.to_string()));
+    if json() {
+        eprintln!("{}", args);
+    } else {
+        println!("{}", args);
+    }
+}
+
+pub fn emit(value: &Value) {
+    if json() {
+        println!("{}", value);
+        EMITTED.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn emitted() -> bool {
+    EMITTED.load(Ordering::Relaxed)
+}
+
+// Takes what this thread has said so far.
+#[cfg(test)]
+pub fn take_transcript() -> Vec<String> {
+    TRANSCRIPT.take()
+}
@@ -0,0 +1,24 @@
+use std::sync::Mutex;
+
+use zeroize::Zeroizing;
+
+// The passphrase entered at a prompt, kept for the rest of this run only.
+// Statics are never dropped, so main wipes it explicitly on the way out.
+static CACHE: Mutex<Option<Zeroizing<String>>> = Mutex::new(None);
+
+fn cache() -> std::sync::MutexGuard<'static, Option<Zeroizing<String>>> {
+    CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn cached() -> Option<Zeroizing<String>> {
+    cache().clone()
+}
+
+pub fn remember(passphrase: &Zeroizing<String>) {
+    *cache() = Some(passphrase.clone());
+}
+
+// Zeroizing wipes the old value as it is dropped here.
+pub fn forget() {
+    cache().take();
+}
@@ -0,0 +1,50 @@
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+use anyhow::Result;
+use hex_str::HexString;
+use l0::AsBytes;
+use zeroize::Zeroizing;
+use zk::Fr;
+
+pub struct Secret(Fr);
+
+impl Secret {
+    pub fn new(secret: Fr) -> Self {
+        Self(secret)
+    }
+    
+    pub fn expose(&self) -> Fr {
+        self.0
+    }
+    
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+    
+    // Decodes through zeroized buffers instead of HexConverter::from_hex, so
+    // the only copy left behind is the argument clap itself holds.
+    pub fn from_hex(hex: &HexString) -> Result<Self> {
+        let text = Zeroizing::new(hex.to_string());
+        let bytes = Zeroizing::new(hex::decode(text.as_str())?);
+        Ok(Self(Fr::dec(&mut bytes.iter().copied())?))
+    }
+    
+    // Same padding as HexConverter::to_hex, with every intermediate wiped.
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        let bytes = Zeroizing::new(self.0.enc().collect::<Vec<u8>>());
+        let mut padded = Zeroizing::new(Vec::with_capacity(32.max(bytes.len())));
+        padded.resize(32usize.saturating_sub(bytes.len()), 0u8);
+        padded.extend_from_slice(&bytes);
+        Zeroizing::new(hex::encode(padded.as_slice()))
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Fr has no Zeroize impl, so overwrite it in place; the volatile
+        // write and fence keep the store from being optimized away.
+        unsafe { ptr::write_volatile(&mut self.0, Fr::from(0u32)) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
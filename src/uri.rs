@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Result};
+use hex_str::HexString;
+use zk::Fr;
+
+use crate::{parse_address, HexConverter};
+
+pub const SCHEME: &str = "wallet:";
+
+pub struct PaymentRequest {
+    pub account: Fr,
+    pub amount: Option<Fr>,
+    pub memo: Option<String>,
+}
+
+impl PaymentRequest {
+    pub fn to_uri(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!("amount={}", amount.to_hex()));
+        }
+        if let Some(memo) = &self.memo {
+            params.push(format!("memo={}", percent_encode(memo)));
+        }
+        
+        let mut uri = format!("{}{}", SCHEME, self.account.to_hex());
+        if !params.is_empty() {
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        uri
+    }
+    
+    pub fn parse(uri: &str) -> Result<Self> {
+        let rest = uri
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| anyhow!("Not a {} URI: {}", SCHEME, uri))?;
+        let (account, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let account: HexString = account
+            .parse()
+            .map_err(|_| anyhow!("Invalid account in payment URI: {}", account))?;
+        
+        let mut request = Self {
+            account: parse_address(&account)?,
+            amount: None,
+            memo: None,
+        };
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Malformed payment URI parameter: {}", param))?;
+            match key {
+                "amount" => {
+                    let amount: HexString = value
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid amount in payment URI: {}", value))?;
+                    request.amount = Some(HexConverter::from_hex(amount)?);
+                }
+                "memo" => request.memo = Some(percent_decode(value)?),
+                _ => log::warn!("Ignoring unknown payment URI parameter: {}", key),
+            }
+        }
+        
+        Ok(request)
+    }
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> Result<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = text
+                .get(i + 1..i + 3)
+                .ok_or_else(|| anyhow!("Truncated escape in payment URI: {}", text))?;
+            decoded.push(u8::from_str_radix(hex, 16)?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
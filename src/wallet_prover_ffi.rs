@@ -1,7 +1,10 @@
 use std::ffi::{CStr, CString};
+use std::fmt;
 use std::os::raw::c_char;
-use anyhow::{anyhow, Result};
+use std::sync::{Mutex, MutexGuard};
+use zeroize::Zeroizing;
 
+#[cfg(not(test))]
 #[link(name = "wallet_prover", kind = "static")]
 extern "C" {
     fn GenerateAddress(secret_hex: *const c_char) -> *mut c_char;
@@ -21,60 +24,101 @@ extern "C" {
     fn FreeString(s: *mut c_char);
 }
 
-pub fn generate_address(secret_hex: &str) -> Result<String> {
-    let c_secret = CString::new(secret_hex)?;
-    
-    unsafe {
-        let result_ptr = GenerateAddress(c_secret.as_ptr());
-        if result_ptr.is_null() {
-            return Err(anyhow!("GenerateAddress returned null"));
-        }
-        
-        let c_str = CStr::from_ptr(result_ptr);
-        let result = c_str.to_str()?.to_string();
-        FreeString(result_ptr);
-        
-        if result.is_empty() {
-            return Err(anyhow!("GenerateAddress failed"));
+#[cfg(test)]
+use stub::{FreeString, GenerateAddress, GenerateProofHashWallet, GenerateProofPermissionless};
+
+// The Go prover keeps global state and is not known to be reentrant, so
+// every call into it is serialized behind this lock, including address
+// derivation. Concurrent callers simply queue.
+static PROVER_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock_prover() -> MutexGuard<'static, ()> {
+    PROVER_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Debug)]
+pub enum ProverError {
+    NulInInput { arg: &'static str },
+    NullPointer { function: &'static str },
+    EmptyResult { function: &'static str },
+    InvalidUtf8 { function: &'static str },
+    BadOutputFormat { fields: usize },
+    BadOutputField { field: &'static str, reason: String },
+}
+
+impl fmt::Display for ProverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProverError::NulInInput { arg } => write!(f, "prover input {} contains a NUL byte", arg),
+            ProverError::NullPointer { function } => write!(f, "{} returned null", function),
+            ProverError::EmptyResult { function } => write!(f, "{} failed (empty result from the prover)", function),
+            ProverError::InvalidUtf8 { function } => write!(f, "{} returned invalid UTF-8", function),
+            ProverError::BadOutputFormat { fields } => write!(
+                f,
+                "Invalid proof output format, expected: proof,vk,address (got {} field(s))",
+                fields
+            ),
+            ProverError::BadOutputField { field, reason } => write!(f, "Invalid {} in proof output: {}", field, reason),
         }
-        
-        Ok(result)
     }
 }
 
+impl std::error::Error for ProverError {}
+
+fn c_arg(arg: &'static str, value: &str) -> Result<CString, ProverError> {
+    CString::new(value).map_err(|_| ProverError::NulInInput { arg })
+}
+
+// Takes ownership of a string returned by the prover and frees it.
+unsafe fn take_result(function: &'static str, result_ptr: *mut c_char) -> Result<String, ProverError> {
+    if result_ptr.is_null() {
+        return Err(ProverError::NullPointer { function });
+    }
+    
+    let result = CStr::from_ptr(result_ptr).to_str().map(|s| s.to_string());
+    FreeString(result_ptr);
+    
+    let result = result.map_err(|_| ProverError::InvalidUtf8 { function })?;
+    if result.is_empty() {
+        return Err(ProverError::EmptyResult { function });
+    }
+    Ok(result)
+}
+
+pub fn generate_address(secret_hex: &str) -> Result<String, ProverError> {
+    let c_secret = Zeroizing::new(c_arg("secret", secret_hex)?);
+    let _guard = lock_prover();
+    
+    unsafe { take_result("GenerateAddress", GenerateAddress(c_secret.as_ptr())) }
+}
+
+// Only public transaction fields cross the FFI here, so unlike the
+// hash-wallet path there is nothing to zeroize.
 pub fn generate_proof_permissionless(
     x_hex: &str,
     y_hex: &str,
     z_hex: &str,
     w_hex: &str,
-) -> Result<(String, String, String)> {
-    let c_x = CString::new(x_hex)?;
-    let c_y = CString::new(y_hex)?;
-    let c_z = CString::new(z_hex)?;
-    let c_w = CString::new(w_hex)?;
-    
-    unsafe {
-        let result_ptr = GenerateProofPermissionless(
-            c_x.as_ptr(),
-            c_y.as_ptr(),
-            c_z.as_ptr(),
-            c_w.as_ptr(),
-        );
-        
-        if result_ptr.is_null() {
-            return Err(anyhow!("GenerateProofPermissionless returned null"));
-        }
-        
-        let c_str = CStr::from_ptr(result_ptr);
-        let result = c_str.to_str()?.to_string();
-        FreeString(result_ptr);
-        
-        if result.is_empty() {
-            return Err(anyhow!("GenerateProofPermissionless failed"));
-        }
-        
-        parse_proof_result(&result)
-    }
+) -> Result<(String, String, String), ProverError> {
+    let c_x = c_arg("x", x_hex)?;
+    let c_y = c_arg("y", y_hex)?;
+    let c_z = c_arg("z", z_hex)?;
+    let c_w = c_arg("w", w_hex)?;
+    let _guard = lock_prover();
+    
+    let result = unsafe {
+        take_result(
+            "GenerateProofPermissionless",
+            GenerateProofPermissionless(
+                c_x.as_ptr(),
+                c_y.as_ptr(),
+                c_z.as_ptr(),
+                c_w.as_ptr(),
+            ),
+        )?
+    };
+    
+    parse_proof_result(&result)
 }
 
 pub fn generate_proof_hash_wallet(
@@ -83,49 +127,209 @@ pub fn generate_proof_hash_wallet(
     y_hex: &str,
     z_hex: &str,
     w_hex: &str,
-) -> Result<(String, String, String)> {
-    let c_secret = CString::new(secret_hex)?;
-    let c_x = CString::new(x_hex)?;
-    let c_y = CString::new(y_hex)?;
-    let c_z = CString::new(z_hex)?;
-    let c_w = CString::new(w_hex)?;
-    
-    unsafe {
-        let result_ptr = GenerateProofHashWallet(
-            c_secret.as_ptr(),
-            c_x.as_ptr(),
-            c_y.as_ptr(),
-            c_z.as_ptr(),
-            c_w.as_ptr(),
-        );
-        
-        if result_ptr.is_null() {
-            return Err(anyhow!("GenerateProofHashWallet returned null"));
+) -> Result<(String, String, String), ProverError> {
+    let c_secret = Zeroizing::new(c_arg("secret", secret_hex)?);
+    let c_x = c_arg("x", x_hex)?;
+    let c_y = c_arg("y", y_hex)?;
+    let c_z = c_arg("z", z_hex)?;
+    let c_w = c_arg("w", w_hex)?;
+    let _guard = lock_prover();
+    
+    let result = unsafe {
+        take_result(
+            "GenerateProofHashWallet",
+            GenerateProofHashWallet(
+                c_secret.as_ptr(),
+                c_x.as_ptr(),
+                c_y.as_ptr(),
+                c_z.as_ptr(),
+                c_w.as_ptr(),
+            ),
+        )?
+    };
+    
+    parse_proof_result(&result)
+}
+
+const ADDRESS_BYTES: usize = 32;
+
+// Proof and vk sizes depend on the circuit, so only the address has an exact
+// length; the other two just have to be non-empty hex.
+fn check_hex_field(field: &'static str, value: &str, expected_len: Option<usize>) -> Result<(), ProverError> {
+    let bad = |reason: String| ProverError::BadOutputField { field, reason };
+    let bytes = hex::decode(value).map_err(|err| bad(err.to_string()))?;
+    if bytes.is_empty() {
+        return Err(bad("empty".to_string()));
+    }
+    if let Some(expected_len) = expected_len {
+        if bytes.len() != expected_len {
+            return Err(bad(format!("expected {} bytes, got {}", expected_len, bytes.len())));
         }
-        
-        let c_str = CStr::from_ptr(result_ptr);
-        let result = c_str.to_str()?.to_string();
-        FreeString(result_ptr);
-        
-        if result.is_empty() {
-            return Err(anyhow!("GenerateProofHashWallet failed"));
+    }
+    Ok(())
+}
+
+fn parse_proof_result(result: &str) -> Result<(String, String, String), ProverError> {
+    let parts: Vec<&str> = result.split(',').collect();
+    if parts.len() != 3 {
+        return Err(ProverError::BadOutputFormat { fields: parts.len() });
+    }
+    
+    check_hex_field("proof", parts[0], None)?;
+    check_hex_field("vk", parts[1], None)?;
+    check_hex_field("address", parts[2], Some(ADDRESS_BYTES))?;
+    Ok((
+        parts[0].to_string(),
+        parts[1].to_string(),
+        parts[2].to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn nul_in_an_input_is_reported_by_argument() {
+        let err = generate_proof_permissionless("00", "0\u{0}0", "00", "00").unwrap_err();
+        assert!(matches!(err, ProverError::NulInInput { arg: "y" }));
+        assert_eq!(err.to_string(), "prover input y contains a NUL byte");
+        assert!(matches!(generate_address("\u{0}"), Err(ProverError::NulInInput { arg: "secret" })));
+    }
+    
+    #[test]
+    fn proof_output_needs_exactly_three_fields() {
+        for (output, fields) in [("aa,bb", 2), ("aa,bb,cc,dd", 4), ("", 1)] {
+            let err = parse_proof_result(output).unwrap_err();
+            assert!(matches!(err, ProverError::BadOutputFormat { fields: found } if found == fields), "{}", output);
         }
+    }
+    
+    #[test]
+    fn proof_output_fields_must_be_hex_and_the_address_32_bytes() {
+        let address = "ab".repeat(ADDRESS_BYTES);
+        let field = |output: &str| match parse_proof_result(output) {
+            Err(ProverError::BadOutputField { field, .. }) => field,
+            other => panic!("{} parsed as {:?}", output, other),
+        };
         
-        parse_proof_result(&result)
+        assert_eq!(field(&format!("zz,0d0e,{}", address)), "proof");
+        assert_eq!(field(&format!("0a0b,0d0,{}", address)), "vk");
+        assert_eq!(field(&format!("0a0b,,{}", address)), "vk");
+        assert_eq!(field("0a0b,0d0e,abcd"), "address");
+        assert!(matches!(parse_proof_result("0a0b,0d0e"), Err(ProverError::BadOutputFormat { fields: 2 })));
+        assert!(parse_proof_result(&format!("0a0b,0d0e,{}", address)).unwrap() == ("0a0b".into(), "0d0e".into(), address));
+    }
+    
+    #[test]
+    fn concurrent_address_derivation_is_serialized() {
+        let threads: Vec<_> = (0..8u8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let secret = format!("{:02x}", i).repeat(ADDRESS_BYTES);
+                    for _ in 0..20 {
+                        assert_eq!(generate_address(&secret).unwrap(), secret);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(stub::peak_concurrency(), 1);
     }
 }
 
-fn parse_proof_result(result: &str) -> Result<(String, String, String)> {
-    let parts: Vec<&str> = result.split(',').collect();
-    if parts.len() == 3 {
-        Ok((
-            parts[0].to_string(),
-            parts[1].to_string(),
-            parts[2].to_string(),
-        ))
-    } else {
-        Err(anyhow!(
-            "Invalid proof output format, expected: proof,vk,address"
-        ))
+// Stands in for the Go prover under test: well-formed canned output, and a
+// record of which entry points were called so tests can tell the hash-wallet
+// and permissionless paths apart. Unless a test installs an address, the
+// "address" of a secret is the secret itself, so derivation stays
+// deterministic and distinct per secret. State is per thread, so tests
+// running in parallel each see their own.
+#[cfg(test)]
+#[allow(non_snake_case)]
+pub mod stub {
+    use std::cell::RefCell;
+    use std::ffi::{CStr, CString};
+    use std::os::raw::c_char;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    
+    pub const PROOF_HEX: &str = "0a0b0c";
+    pub const VK_HEX: &str = "0d0e0f";
+    
+    // Unlike the rest of the stub's state these are shared by all threads:
+    // how many calls are inside the stub right now, and the most there ever
+    // were at once.
+    static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+    
+    thread_local! {
+        static ADDRESS: RefCell<String> = const { RefCell::new(String::new()) };
+        static CALLS: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+    }
+    
+    // Makes the stub report `address` and clears its call record.
+    pub fn install(address: &str) {
+        ADDRESS.set(address.to_string());
+        CALLS.take();
+    }
+    
+    pub fn calls() -> Vec<&'static str> {
+        CALLS.with_borrow(|calls| calls.clone())
+    }
+    
+    pub fn peak_concurrency() -> usize {
+        PEAK.load(Ordering::SeqCst)
+    }
+    
+    fn respond(function: &'static str, result: String) -> *mut c_char {
+        CALLS.with_borrow_mut(|calls| calls.push(function));
+        CString::new(result).unwrap().into_raw()
+    }
+    
+    unsafe fn address_of(secret_hex: *const c_char) -> String {
+        let installed = ADDRESS.with_borrow(String::clone);
+        if !installed.is_empty() || secret_hex.is_null() {
+            return installed;
+        }
+        CStr::from_ptr(secret_hex).to_string_lossy().into_owned()
+    }
+    
+    fn proof_output(address: String) -> String {
+        format!("{},{},{}", PROOF_HEX, VK_HEX, address)
+    }
+    
+    // Lingers a moment so overlapping callers would show up in PEAK.
+    pub(super) unsafe fn GenerateAddress(secret_hex: *const c_char) -> *mut c_char {
+        let active = ACTIVE.fetch_add(1, Ordering::SeqCst) + 1;
+        PEAK.fetch_max(active, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(1));
+        let address = address_of(secret_hex);
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+        respond("GenerateAddress", address)
+    }
+    
+    pub(super) unsafe fn GenerateProofPermissionless(
+        _x_hex: *const c_char,
+        _y_hex: *const c_char,
+        _z_hex: *const c_char,
+        _w_hex: *const c_char,
+    ) -> *mut c_char {
+        respond("GenerateProofPermissionless", proof_output(address_of(std::ptr::null())))
+    }
+    
+    pub(super) unsafe fn GenerateProofHashWallet(
+        secret_hex: *const c_char,
+        _x_hex: *const c_char,
+        _y_hex: *const c_char,
+        _z_hex: *const c_char,
+        _w_hex: *const c_char,
+    ) -> *mut c_char {
+        respond("GenerateProofHashWallet", proof_output(address_of(secret_hex)))
+    }
+    
+    pub(super) unsafe fn FreeString(s: *mut c_char) {
+        drop(CString::from_raw(s));
     }
 }